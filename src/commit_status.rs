@@ -0,0 +1,106 @@
+use std::{
+    thread,
+    sync::{Mutex, mpsc::{channel, Sender}}
+};
+use anyhow::Error;
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+
+use crate::{
+    model::project::{
+        log_pattern::LogLineFields,
+        commit_status::{CommitStatusOptions, Forge}
+    },
+    notifier::post_json
+};
+
+lazy_static! {
+    // Statuses are sent one at a time by a single thread so a quick run can't have its result overwritten by its pending status
+    static ref REPORTER: Mutex<Option<Sender<StatusUpdate>>> = Mutex::new(None);
+}
+
+/// Forges limit the length of status descriptions. GitHub allows 140 characters
+const MAX_DESCRIPTION_LENGTH: usize = 140;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommitState {
+    Pending,
+    Success,
+    Failure,
+    Canceled, // A newer commit or shutdown stopped the run
+}
+
+impl CommitState {
+    fn as_forge_state(&self, forge: Forge) -> &'static str {
+        match (self, forge) {
+            (CommitState::Pending, Forge::GitLab) => "running",
+            (CommitState::Pending, _) => "pending",
+            (CommitState::Success, _) => "success",
+            (CommitState::Failure, Forge::GitLab) => "failed",
+            (CommitState::Failure, _) => "failure",
+            (CommitState::Canceled, Forge::GitLab) => "canceled",
+            (CommitState::Canceled, _) => "error",
+        }
+    }
+}
+
+struct StatusUpdate {
+    options: CommitStatusOptions,
+    state: CommitState,
+    fields: LogLineFields,
+    description: String,
+}
+
+/// Queues a status for the commit a procedure ran against
+pub fn report(options: &CommitStatusOptions, state: CommitState, fields: &LogLineFields, description: &str) {
+    let update = StatusUpdate {
+        options: options.clone(),
+        state: state,
+        fields: fields.clone(),
+        description: description.to_string(),
+    };
+    let mut reporter = REPORTER.lock().unwrap();
+    let sender: &Sender<StatusUpdate> = reporter.get_or_insert_with(|| {
+        let (sender, receiver) = channel::<StatusUpdate>();
+        thread::spawn(move || {
+            for update in receiver {
+                if let Err(e) = send(&update) {
+                    warn!("[{}] Failed to report {} status of commit {}: {}", update.fields.procedure_name, update.state.as_forge_state(update.options.forge), update.fields.commit, e);
+                }
+            }
+        });
+        sender
+    });
+    let _ = sender.send(update);
+}
+
+fn send(update: &StatusUpdate) -> Result<(), Error> {
+    let options: &CommitStatusOptions = &update.options;
+    let token: String = options.token.resolve()?;
+    let state: &str = update.state.as_forge_state(options.forge);
+    let context: String = options.context.format_event(&update.fields, state, "");
+    let target_url: Option<String> = options.target_url.as_ref().map(|t| t.format_event(&update.fields, state, ""));
+    let description: String = update.description.chars().take(MAX_DESCRIPTION_LENGTH).collect();
+
+    let (url, header, mut body) = match options.forge {
+        Forge::GitHub => (
+            format!("{}/repos/{}/statuses/{}", options.api_url, options.repository, update.fields.commit),
+            format!("Authorization: Bearer {}", token),
+            json!({"state": state, "context": context, "description": description})
+        ),
+        Forge::GitLab => (
+            format!("{}/projects/{}/statuses/{}", options.api_url, options.repository.replace('/', "%2F"), update.fields.commit),
+            format!("PRIVATE-TOKEN: {}", token),
+            json!({"state": state, "name": context, "ref": update.fields.branch, "description": description})
+        ),
+        Forge::Gitea => (
+            format!("{}/repos/{}/statuses/{}", options.api_url, options.repository, update.fields.commit),
+            format!("Authorization: token {}", token),
+            json!({"state": state, "context": context, "description": description})
+        ),
+    };
+    if let Some(target_url) = target_url {
+        body["target_url"] = Value::from(target_url);
+    }
+    post_json(&url, &[header, "Accept: application/json".to_string()], &body)
+}
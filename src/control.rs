@@ -0,0 +1,313 @@
+use std::{
+    thread,
+    collections::HashMap,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, mpsc::RecvTimeoutError, atomic::{AtomicUsize, Ordering}},
+    time::Duration
+};
+use anyhow::{Error, anyhow};
+use serde_json::Value;
+
+use crate::{
+    model::control::ControlOptions,
+    output_buffer::{self, SharedOutputBuffer, OutputLine},
+    history::{self, HistoryFilter, DEFAULT_QUERY_LIMIT}
+};
+
+/// Lines returned by /logs unless the lines parameter is set
+const DEFAULT_TAIL_LINES: usize = 100;
+/// Followers are sent a comment this often so disconnected clients are noticed while a procedure is quiet
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// Time a client has to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Upper bound for the request line and headers
+const MAX_REQUEST_SIZE: u64 = 16 * 1024;
+/// Connections served at once. Each of them has its own thread
+const MAX_CONNECTIONS: usize = 64;
+/// Time a client has to accept a response or event before it is disconnected
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Connections currently being served
+static OPEN_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts a connection as open until it is dropped
+struct ConnectionSlot;
+
+impl ConnectionSlot {
+    fn acquire() -> Option<ConnectionSlot> {
+        let previous: usize = OPEN_CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+        if previous >= MAX_CONNECTIONS {
+            OPEN_CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(ConnectionSlot)
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        OPEN_CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Starts serving the control interface in the background
+/// Routes:
+/// GET /procedures - Procedures which ran since Influo started as JSON
+/// GET /logs?project=&branch=&procedure=&lines= - Last lines of output of a procedure as text
+/// GET /logs/follow?project=&branch=&procedure=&lines= - Last lines followed by new ones as server-sent events
+/// GET /history?project=&branch=&procedure=&status=&limit= - Finished runs as JSON, newest first
+/// Every route requires an Authorization: Bearer header if a token is configured
+pub fn start(options: &ControlOptions) -> Result<(), Error> {
+    let listener: TcpListener = TcpListener::bind(options.address)
+        .map_err(|e| anyhow!("Unable to start control interface on {}: {}", options.address, e))?;
+    if options.token.is_none() {
+        if options.address.ip().is_loopback() {
+            info!("Control interface has no token. Every local user can read procedure output");
+        } else {
+            warn!("Control interface is listening on {} which is not a loopback address. It has no authentication", options.address);
+        }
+    }
+    let token: Option<Arc<String>> = options.token.clone().map(Arc::new);
+    output_buffer::enable(options.buffer_lines);
+    info!("Control interface listening on {}", options.address);
+
+    thread::spawn(move || {
+        for connection in listener.incoming() {
+            match connection {
+                Ok(mut stream) => {
+                    let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+                    let slot: ConnectionSlot = match ConnectionSlot::acquire() {
+                        Some(s) => s,
+                        None => {
+                            debug!("Rejected control connection since {} connections are open", MAX_CONNECTIONS);
+                            let _ = respond(&mut stream, 503, "text/plain", "Too many connections\n");
+                            continue;
+                        }
+                    };
+                    // Followers keep their connection open so every connection gets its own thread
+                    let token: Option<Arc<String>> = token.clone();
+                    thread::spawn(move || {
+                        let _slot = slot;
+                        if let Err(e) = handle_connection(stream, token.as_deref().map(|t| t.as_str())) {
+                            debug!("Control connection closed: {}", e);
+                        }
+                    });
+                },
+                Err(e) => debug!("Failed to accept control connection: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    authorization: Option<String>,
+}
+
+impl Request {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.query.get(name).map(|v| v.as_str())
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, token: Option<&str>) -> Result<(), Error> {
+    let request: Request = match read_request(&stream) {
+        Ok(r) => r,
+        Err(e) => return respond(&mut stream, 400, "text/plain", &format!("{}\n", e)),
+    };
+    if let Some(token) = token {
+        let authorized: bool = match request.authorization.as_deref().and_then(|a| a.split_once(' ')) {
+            Some((scheme, given)) if scheme.eq_ignore_ascii_case("bearer") => constant_time_eq(given.trim().as_bytes(), token.as_bytes()),
+            _ => false
+        };
+        if !authorized {
+            return respond(&mut stream, 401, "text/plain", "A valid bearer token is required\n");
+        }
+    }
+    if request.method != "GET" {
+        return respond(&mut stream, 405, "text/plain", "Only GET is supported\n");
+    }
+
+    match request.path.as_str() {
+        "/procedures" => {
+            let procedures: Vec<Value> = output_buffer::find(None, None, None).iter().map(|b| b.lock().unwrap().to_json()).collect();
+            respond(&mut stream, 200, "application/json", &format!("{}\n", Value::from(procedures)))
+        },
+        "/logs" => {
+            let (buffer, lines) = match select_buffer(&request) {
+                Ok(b) => b,
+                Err((status, message)) => return respond(&mut stream, status, "text/plain", &message),
+            };
+            let text: String = buffer.lock().unwrap().lines(lines).iter().map(|l| format!("{}\n", l.to_text())).collect();
+            respond(&mut stream, 200, "text/plain; charset=utf-8", &text)
+        },
+        "/logs/follow" => {
+            let (buffer, lines) = match select_buffer(&request) {
+                Ok(b) => b,
+                Err((status, message)) => return respond(&mut stream, status, "text/plain", &message),
+            };
+            follow(&mut stream, &buffer, lines)
+        },
+        "/history" => {
+            let limit: usize = match request.param("limit").map(|l| l.parse()) {
+                Some(Ok(l)) if l > 0 => l,
+                Some(_) => return respond(&mut stream, 400, "text/plain", "limit must be a positive number\n"),
+                None => DEFAULT_QUERY_LIMIT
+            };
+            let filter = HistoryFilter {
+                project_url: request.param("project").map(|p| p.to_string()),
+                branch: request.param("branch").map(|b| b.to_string()),
+                procedure: request.param("procedure").map(|p| p.to_string()),
+                status: request.param("status").map(|s| s.to_string()),
+                limit: limit,
+            };
+            match history::query_enabled(&filter) {
+                Ok(runs) => respond(&mut stream, 200, "application/json", &format!("{}\n", Value::from(runs))),
+                Err(e) => respond(&mut stream, 500, "text/plain", &format!("{}\n", e)),
+            }
+        },
+        _ => respond(&mut stream, 404, "text/plain", "Not found\n"),
+    }
+}
+
+/// Finds the single buffer matching the project, branch and procedure parameters
+fn select_buffer(request: &Request) -> Result<(SharedOutputBuffer, usize), (u16, String)> {
+    let lines: usize = match request.param("lines") {
+        Some(l) => match l.parse() {
+            Ok(n) => n,
+            Err(_) => return Err((400, "lines must be a number\n".to_string())),
+        },
+        None => DEFAULT_TAIL_LINES
+    };
+    let mut buffers: Vec<SharedOutputBuffer> = output_buffer::find(request.param("project"), request.param("branch"), request.param("procedure"));
+    match buffers.len() {
+        0 => Err((404, "No output found for the given project, branch and procedure\n".to_string())),
+        1 => Ok((buffers.pop().unwrap(), lines)),
+        n => Err((400, format!("{} procedures match. Narrow it down using the project, branch and procedure parameters\n", n))),
+    }
+}
+
+/// Streams output as server-sent events until the client disconnects or falls too far behind
+fn follow(stream: &mut TcpStream, buffer: &SharedOutputBuffer, lines: usize) -> Result<(), Error> {
+    let (recent, receiver) = buffer.lock().unwrap().follow(lines);
+    let mut writer = BufWriter::new(stream);
+    writer.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n")?;
+    for line in recent {
+        write_event(&mut writer, &line)?;
+    }
+    writer.flush()?;
+    loop {
+        match receiver.recv_timeout(KEEPALIVE_INTERVAL) {
+            Ok(line) => {
+                write_event(&mut writer, &line)?;
+                // Lines which are already queued are sent together so bursts of output don't need a write per line
+                while let Ok(line) = receiver.try_recv() {
+                    write_event(&mut writer, &line)?;
+                }
+            },
+            Err(RecvTimeoutError::Timeout) => writer.write_all(b": keepalive\n\n")?,
+            Err(RecvTimeoutError::Disconnected) => {
+                writer.write_all(b": output was produced faster than it was read\n\n")?;
+                writer.flush()?;
+                return Ok(());
+            },
+        }
+        writer.flush()?;
+    }
+}
+
+fn write_event(writer: &mut impl Write, line: &OutputLine) -> Result<(), Error> {
+    writer.write_all(format!("event: {}\ndata: {}\n\n", line.stream, line.to_json()).as_bytes())?;
+    Ok(())
+}
+
+fn respond(stream: &mut TcpStream, status: u16, content_type: &str, body: &str) -> Result<(), Error> {
+    let reason: &str = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    let response: String = format!("HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, reason, content_type, body.len(), body);
+    stream.write_all(response.as_bytes())?;
+    Ok(())
+}
+
+/// Reads the request line and the Authorization header. Other headers are skipped since no route needs them
+fn read_request(stream: &TcpStream) -> Result<Request, Error> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_SIZE));
+    let mut request_line: String = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut authorization: Option<String> = None;
+    loop {
+        let mut header: String = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(m), Some(t)) => (m, t),
+        _ => return Err(anyhow!("Malformed request line")),
+    };
+    let (path, raw_query) = match target.split_once('?') {
+        Some((p, q)) => (p, q),
+        None => (target, ""),
+    };
+    let query: HashMap<String, String> = raw_query.split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.split_once('=') {
+            Some((k, v)) => (percent_decode(k), percent_decode(v)),
+            None => (percent_decode(p), String::new()),
+        })
+        .collect();
+
+    Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query,
+        authorization: authorization,
+    })
+}
+
+/// Compares without returning early so the token can't be guessed from response times
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |difference, (x, y)| difference | (x ^ y)) == 0
+}
+
+/// Decodes a query string component. Invalid escapes are kept as they are
+fn percent_decode(s: &str) -> String {
+    let bytes: &[u8] = s.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i: usize = 0;
+    while i < bytes.len() {
+        let escaped: Option<u8> = bytes.get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], escaped) {
+            (b'+', _) => decoded.push(b' '),
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 2;
+            },
+            (b, _) => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,173 @@
+use std::{
+    fs,
+    process::Stdio
+};
+use anyhow::{Error, anyhow};
+use futures::future::{BoxFuture, FutureExt};
+use tokio::process::Child;
+
+use crate::model::project::{
+    checkout::CheckoutOptions,
+    command::ProcedureCommand,
+    container::ContainerOptions,
+    limits::ResourceLimits,
+    user::ProcessUser
+};
+use super::{Executor, ExecutorProcess, ExecutionContext, ChildExit, OutputStream, ProcessSignal, open_local_input, write_literal_input, local::checkout_locally};
+
+/// Path the repository is mounted at inside containers
+const CONTAINER_WORKSPACE: &str = "/workspace";
+
+/// Runs commands inside new containers with the repository bind-mounted
+/// Containers are limited by the container runtime instead of cgroups created by Influo
+pub struct ContainerExecutor {
+    context: ExecutionContext,
+    container: ContainerOptions,
+    limits: ResourceLimits,
+    user: Option<ProcessUser>,
+    runs: u32, // Containers started by this run. Together with the run id keeps names unique while a stopped one is removed
+}
+
+impl ContainerExecutor {
+    pub fn new(context: ExecutionContext, container: ContainerOptions, limits: ResourceLimits, user: Option<ProcessUser>) -> ContainerExecutor {
+        ContainerExecutor {
+            context: context,
+            container: container,
+            limits: limits,
+            user: user,
+            runs: 0,
+        }
+    }
+}
+
+impl Executor for ContainerExecutor {
+    /// The repository is checked out on the host and mounted into the containers
+    fn checkout(&mut self, remote_url: &str, branch: &str, checkout: &CheckoutOptions) -> Result<(), Error> {
+        checkout_locally(&self.context, remote_url, branch, checkout, self.user.as_ref())
+    }
+
+    /// The container is removed once the command exits
+    fn spawn(&mut self, command: &ProcedureCommand) -> Result<Box<dyn ExecutorProcess>, Error> {
+        let container_name: String = format!("influo-{}-{}-{}", self.context.run_name, self.context.run_id, self.runs);
+        self.runs += 1;
+        let container = &self.container;
+        let limits = &self.limits;
+        let environment: Vec<(String, String)> = self.context.command_environment(command);
+        let working_directory: String = match &command.cwd {
+            Some(cwd) => format!("{}/{}", CONTAINER_WORKSPACE, cwd),
+            None => CONTAINER_WORKSPACE.to_string()
+        };
+
+        let absolute_repository_path = fs::canonicalize(&self.context.repository_path)?;
+        let mut args: Vec<String> = vec![
+            "run".to_string(),
+            "--rm".to_string(),
+            "--init".to_string(), // Signals are forwarded to the command and zombies are reaped
+            "--name".to_string(), container_name.clone(),
+            "--volume".to_string(), format!("{}:{}", absolute_repository_path.to_string_lossy(), CONTAINER_WORKSPACE),
+            "--workdir".to_string(), working_directory,
+        ];
+        if command.stdin.is_some() {
+            args.push("--interactive".to_string());
+        }
+
+        // Values are inherited from the runtime CLI process so they don't show up in the process list
+        for (key, _) in &environment {
+            args.push("--env".to_string());
+            args.push(key.clone());
+        }
+        for key in &container.pass_env {
+            args.push("--env".to_string());
+            args.push(key.clone());
+        }
+
+        if let Some(u) = &self.user {
+            // Procedures with a container and a group always have a user
+            match (u.uid, u.gid) {
+                (Some(uid), Some(gid)) => args.push(format!("--user={}:{}", uid, gid)),
+                (Some(uid), None) => args.push(format!("--user={}", uid)),
+                _ => ()
+            }
+        }
+        if let Some(memory) = limits.memory {
+            args.push(format!("--memory={}", memory));
+        }
+        if let Some(cpu_weight) = limits.cpu_weight {
+            args.push(format!("--cpu-shares={}", cpu_weight_to_shares(cpu_weight)));
+        }
+        if let Some(processes) = limits.processes {
+            args.push(format!("--pids-limit={}", processes));
+        }
+        if let Some(open_files) = limits.open_files {
+            args.push(format!("--ulimit=nofile={}:{}", open_files, open_files));
+        }
+
+        args.extend(container.options.iter().cloned());
+        args.push(container.image.clone());
+        args.extend(self.context.shell.command_line(&command.command)?);
+
+        // Input files are read on the host like the rest of the checkout
+        let mut child: Child = tokio::process::Command::new(&container.runtime)
+                .args(&args)
+                .envs(environment)
+                .stdin(open_local_input(command, &self.context)?)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?;
+        write_literal_input(&mut child, command);
+        Ok(Box::new(ContainerProcess {
+            child: child,
+            runtime: container.runtime.clone(),
+            container_name: container_name,
+        }))
+    }
+}
+
+/// The runtime CLI attached to a running container
+pub struct ContainerProcess {
+    child: Child,
+    runtime: String,
+    container_name: String,
+}
+
+impl ExecutorProcess for ContainerProcess {
+    fn stdout(&mut self) -> Option<OutputStream> {
+        self.child.stdout.take().map(|s| Box::new(s) as OutputStream)
+    }
+
+    fn stderr(&mut self) -> Option<OutputStream> {
+        self.child.stderr.take().map(|s| Box::new(s) as OutputStream)
+    }
+
+    fn wait(&mut self) -> BoxFuture<'_, ChildExit> {
+        async move {
+            ChildExit::from_status(self.child.wait().await)
+        }.boxed()
+    }
+
+    /// Signals are sent through the runtime since killing the runtime CLI alone would leave the container running
+    fn signal(&mut self, signal: ProcessSignal) -> Result<(), Error> {
+        let runtime_signal: &str = match signal {
+            ProcessSignal::Terminate => "TERM",
+            ProcessSignal::Kill => "KILL",
+        };
+        let status = std::process::Command::new(&self.runtime)
+                .args(["kill", "--signal", runtime_signal, &self.container_name])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()?;
+        if signal == ProcessSignal::Kill {
+            let _ = self.child.start_kill(); // The CLI may already have exited with the container
+        }
+        if !status.success() {
+            return Err(anyhow!("Unable to signal container {}. It may already be stopped.", self.container_name));
+        }
+
+        Ok(())
+    }
+}
+
+/// Converts a cgroup v2 cpu.weight (default 100) to docker cpu shares (default 1024)
+fn cpu_weight_to_shares(cpu_weight: u64) -> u64 {
+    (cpu_weight * 1024 / 100).max(2)
+}
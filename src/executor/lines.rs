@@ -0,0 +1,107 @@
+use std::time::Duration;
+use tokio::{
+    io::AsyncReadExt,
+    time::timeout
+};
+
+use super::OutputStream;
+
+/// Longer lines are split so a command writing binary data can't exhaust memory
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 16 * 1024;
+/// Time after which output without a trailing newline is emitted as a line
+pub const DEFAULT_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// Reads the output of a command line by line
+/// Invalid UTF-8 is replaced instead of failing and carriage returns before newlines are dropped
+pub struct LineReader {
+    stream: OutputStream,
+    buffer: Vec<u8>,
+    max_line_length: usize,
+    flush_timeout: Duration,
+    eof: bool,
+}
+
+impl LineReader {
+    pub fn new(stream: OutputStream, max_line_length: usize, flush_timeout: Duration) -> LineReader {
+        LineReader {
+            stream: stream,
+            buffer: Vec::new(),
+            max_line_length: max_line_length.max(4), // Room for at least one character
+            flush_timeout: flush_timeout,
+            eof: false,
+        }
+    }
+
+    /// Returns the next line or None once the stream ended
+    /// A partial line is returned if nothing else was written within the flush timeout
+    pub async fn next_line(&mut self) -> Option<String> {
+        let mut chunk: [u8; READ_CHUNK_SIZE] = [0; READ_CHUNK_SIZE];
+        loop {
+            if let Some(newline) = self.buffer.iter().take(self.max_line_length + 1).position(|b| *b == b'\n') {
+                let mut line: Vec<u8> = self.buffer.drain(..=newline).collect();
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return Some(decode(line));
+            }
+            if self.buffer.len() >= self.max_line_length {
+                let split: usize = split_point(&self.buffer, self.max_line_length);
+                return Some(decode(self.buffer.drain(..split).collect()));
+            }
+            if self.eof {
+                if self.buffer.is_empty() {
+                    return None;
+                }
+                return Some(decode(std::mem::take(&mut self.buffer)));
+            }
+
+            let read = if self.buffer.is_empty() {
+                Ok(self.stream.read(&mut chunk).await)
+            } else {
+                timeout(self.flush_timeout, self.stream.read(&mut chunk)).await
+            };
+            match read {
+                Ok(Ok(0)) => self.eof = true,
+                Ok(Ok(n)) => self.buffer.extend_from_slice(&chunk[..n]),
+                Ok(Err(e)) => {
+                    debug!("Failed to read command output: {}", e);
+                    self.eof = true;
+                },
+                // Nothing was written for a while so the partial line is flushed
+                Err(_) => return Some(decode(std::mem::take(&mut self.buffer))),
+            }
+        }
+    }
+}
+
+fn decode(bytes: Vec<u8>) -> String {
+    match String::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+    }
+}
+
+/// Splits before a character which would otherwise be cut in half
+fn split_point(buffer: &[u8], max_length: usize) -> usize {
+    // Characters are at most 4 bytes long so the last one starts within the last 4 bytes
+    // UTF-8 continuation bytes start with 0b10
+    let start: Option<usize> = (max_length - 4..max_length).rev().find(|i| buffer[*i] & 0b1100_0000 != 0b1000_0000);
+    let start: usize = match start {
+        Some(s) => s,
+        None => return max_length, // Not valid UTF-8 anyway
+    };
+    let width: usize = match buffer[start] {
+        b if b & 0b1000_0000 == 0 => 1,
+        b if b & 0b1110_0000 == 0b1100_0000 => 2,
+        b if b & 0b1111_0000 == 0b1110_0000 => 3,
+        _ => 4,
+    };
+    if start + width > max_length {
+        start
+    } else {
+        max_length
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    process::Stdio,
+    sync::Arc
+};
+use anyhow::Error;
+use futures::future::{BoxFuture, FutureExt};
+use tokio::process::Child;
+
+use crate::{
+    model::project::{
+        checkout::CheckoutOptions,
+        command::ProcedureCommand,
+        limits::ResourceLimits,
+        user::ProcessUser
+    },
+    system_cmd::setup_git_repository,
+    limits::{Cgroup, apply_process_limits},
+    privileges::apply_process_user
+};
+use super::{Executor, ExecutorProcess, ExecutionContext, ChildExit, OutputStream, ProcessSignal, open_local_input, write_literal_input};
+
+/// Runs commands as child processes of Influo
+pub struct LocalExecutor {
+    context: ExecutionContext,
+    limits: ResourceLimits,
+    user: Option<ProcessUser>,
+    cgroup: Option<Arc<Cgroup>>,
+}
+
+impl LocalExecutor {
+    pub fn new(context: ExecutionContext, limits: ResourceLimits, user: Option<ProcessUser>) -> LocalExecutor {
+        // Every process of the procedure is placed in the same cgroup so limits apply to the whole tree
+        // Each run gets its own cgroup since the previous run may still be stopping and remove its cgroup once it stopped
+        let cgroup: Option<Arc<Cgroup>> = match &limits.cgroup_root {
+            Some(root) if cfg!(unix) && !limits.is_empty() => match Cgroup::create(root, &format!("{}-{}", context.run_name, context.run_id), &limits) {
+                Ok(c) => Some(Arc::new(c)),
+                Err(e) => {
+                    warn!("[{}] Failed to create cgroup in {}. Only rlimits will be applied: {}", context.procedure_name, root, e);
+                    None
+                }
+            },
+            _ => None
+        };
+
+        LocalExecutor {
+            context: context,
+            limits: limits,
+            user: user,
+            cgroup: cgroup,
+        }
+    }
+}
+
+impl Executor for LocalExecutor {
+    fn checkout(&mut self, remote_url: &str, branch: &str, checkout: &CheckoutOptions) -> Result<(), Error> {
+        checkout_locally(&self.context, remote_url, branch, checkout, self.user.as_ref())
+    }
+
+    /// Procedure commands are not guaranteed to end
+    fn spawn(&mut self, command: &ProcedureCommand) -> Result<Box<dyn ExecutorProcess>, Error> {
+        let args: Vec<String> = self.context.shell.command_line(&command.command)?;
+        let working_directory: String = self.context.working_directory(command);
+        let mut procedure_command = tokio::process::Command::new(&args[0]);
+        procedure_command
+                .current_dir(&working_directory)
+                .args(&args[1..])
+                .envs(self.context.command_environment(command))
+                .stdin(open_local_input(command, &self.context)?)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+        apply_process_limits(&mut procedure_command, &self.limits, self.cgroup.as_deref())?;
+        if let Some(u) = &self.user {
+            apply_process_user(&mut procedure_command, u)?;
+        }
+
+        let oom_kills_before: u64 = self.cgroup.as_ref().map_or(0, |c| c.oom_kills());
+        let mut child: Child = procedure_command.spawn()?;
+        write_literal_input(&mut child, command);
+        Ok(Box::new(LocalProcess {
+            child: child,
+            cgroup: self.cgroup.clone(),
+            oom_kills_before: oom_kills_before,
+        }))
+    }
+}
+
+impl Drop for LocalExecutor {
+    fn drop(&mut self) {
+        if let Some(c) = &self.cgroup {
+            c.remove();
+        }
+    }
+}
+
+/// Clones or updates the repository on the host Influo runs on
+/// The checkout is made by the user the commands run as so they can modify it
+pub fn checkout_locally(context: &ExecutionContext, remote_url: &str, branch: &str, checkout: &CheckoutOptions, user: Option<&ProcessUser>) -> Result<(), Error> {
+    setup_git_repository(remote_url, &context.deploy_path, branch, checkout, user)?;
+
+    Ok(())
+}
+
+pub struct LocalProcess {
+    child: Child,
+    cgroup: Option<Arc<Cgroup>>,
+    oom_kills_before: u64,
+}
+
+impl ExecutorProcess for LocalProcess {
+    fn stdout(&mut self) -> Option<OutputStream> {
+        self.child.stdout.take().map(|s| Box::new(s) as OutputStream)
+    }
+
+    fn stderr(&mut self) -> Option<OutputStream> {
+        self.child.stderr.take().map(|s| Box::new(s) as OutputStream)
+    }
+
+    fn wait(&mut self) -> BoxFuture<'_, ChildExit> {
+        async move {
+            let mut exit = ChildExit::from_status(self.child.wait().await);
+            exit.oom_killed = self.cgroup.as_ref().is_some_and(|c| c.oom_kills() > self.oom_kills_before);
+            exit
+        }.boxed()
+    }
+
+    fn signal(&mut self, signal: ProcessSignal) -> Result<(), Error> {
+        match signal {
+            #[cfg(unix)]
+            ProcessSignal::Terminate => {
+                // The child was already reaped if it has no id
+                if let Some(pid) = self.child.id() {
+                    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+                        return Err(std::io::Error::last_os_error().into());
+                    }
+                }
+                Ok(())
+            },
+            _ => Ok(self.child.start_kill()?)
+        }
+    }
+
+    fn id(&self) -> Option<u32> {
+        self.child.id()
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    fs::{File, OpenOptions},
+    path::{Path, PathBuf},
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    time::Duration,
+    process::{Stdio, ExitStatus}
+};
+use anyhow::{anyhow, Error};
+use futures::future::BoxFuture;
+use tokio::{
+    process::Child,
+    io::{AsyncRead, AsyncWriteExt}
+};
+
+pub mod local;
+pub mod container;
+pub mod ssh;
+pub mod lines;
+
+use crate::model::project::{
+    procedure::Procedure,
+    checkout::CheckoutOptions,
+    command::{ProcedureCommand, CommandInput},
+    shell::Shell
+};
+use local::LocalExecutor;
+use container::ContainerExecutor;
+use ssh::SshExecutor;
+
+/// Output of a command which is read line by line
+pub type OutputStream = Box<dyn AsyncRead + Send + Unpin>;
+
+/// Everything an executor needs to know about the procedure it runs commands for
+#[derive(Debug, Clone)]
+pub struct ExecutionContext {
+    pub procedure_name: String,
+    pub run_name: String, // Unique per project, branch and procedure. Used for naming cgroups and containers
+    pub run_id: String, // Unique per run. Keeps resources of a run which is still stopping apart from those of the next run
+    pub deploy_path: String,
+    pub repository_path: String, // Checkout of the branch inside the deploy path
+    pub shell: Shell,
+    pub environment: Vec<(String, String)>,
+    pub max_line_length: usize, // Output of remote hosts is merged line by line using the limits of the procedure
+    pub line_flush_timeout: Duration,
+}
+
+impl ExecutionContext {
+    /// Directory a command runs in
+    pub fn working_directory(&self, command: &ProcedureCommand) -> String {
+        match &command.cwd {
+            Some(cwd) => format!("{}/{}", self.repository_path, cwd),
+            None => self.repository_path.clone()
+        }
+    }
+
+    /// Environment of a command. Variables of the command come last so they take precedence
+    pub fn command_environment(&self, command: &ProcedureCommand) -> Vec<(String, String)> {
+        self.environment.iter().chain(command.env.iter()).cloned().collect()
+    }
+}
+
+/// Runs the commands of a procedure
+/// A new executor is created for every run of a procedure and dropped once it completed
+pub trait Executor: Send {
+    /// Clones or updates the repository where the commands run
+    fn checkout(&mut self, remote_url: &str, branch: &str, checkout: &CheckoutOptions) -> Result<(), Error>;
+
+    /// Starts a command in its working directory inside the repository
+    /// Must be called from within a tokio runtime
+    fn spawn(&mut self, command: &ProcedureCommand) -> Result<Box<dyn ExecutorProcess>, Error>;
+}
+
+/// A command started by an executor
+pub trait ExecutorProcess: Send {
+    /// Takes the stdout of the command. Returns None once taken
+    /// Must be called from within a tokio runtime
+    fn stdout(&mut self) -> Option<OutputStream>;
+
+    /// Takes the stderr of the command. Returns None once taken
+    /// Must be called from within a tokio runtime
+    fn stderr(&mut self) -> Option<OutputStream>;
+
+    /// Resolves once the command exited
+    fn wait(&mut self) -> BoxFuture<'_, ChildExit>;
+
+    /// Sends a signal to the command without waiting for it to exit
+    fn signal(&mut self, signal: ProcessSignal) -> Result<(), Error>;
+
+    /// Process id of the command if it runs as a single process on the host Influo runs on
+    fn id(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// Signals which can be sent to a running command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProcessSignal {
+    Terminate, // Asks the command to exit
+    Kill, // Forces the command to exit
+}
+
+/// Exit status of a command
+#[derive(Debug, Clone)]
+pub struct ChildExit {
+    pub success: bool,
+    pub code: i32, // 1 if the child was terminated by a signal
+    pub signal: Option<i32>, // Signal which terminated the child (Unix only)
+    pub oom_killed: bool, // The OOM killer killed a process of the command
+}
+
+impl ChildExit {
+    pub fn from_status(status: std::io::Result<ExitStatus>) -> ChildExit {
+        let status: ExitStatus = match status {
+            Ok(s) => s,
+            Err(_) => return ChildExit {
+                success: false,
+                code: 1,
+                signal: None,
+                oom_killed: false,
+            }
+        };
+        #[cfg(unix)]
+        let signal: Option<i32> = {
+            use std::os::unix::process::ExitStatusExt;
+            status.signal()
+        };
+        #[cfg(not(unix))]
+        let signal: Option<i32> = None;
+        ChildExit {
+            success: status.success(),
+            code: status.code().unwrap_or(1),
+            signal: signal,
+            oom_killed: false,
+        }
+    }
+}
+
+/// Input of a command started on the host Influo runs on
+/// Literal input is piped and has to be written using write_literal_input once the command started
+pub fn open_local_input(command: &ProcedureCommand, context: &ExecutionContext) -> Result<Stdio, Error> {
+    match &command.stdin {
+        Some(CommandInput::File(file)) => {
+            let path: PathBuf = Path::new(&context.working_directory(command)).join(file);
+            Ok(Stdio::from(open_checkout_file(&path, &context.repository_path)?))
+        },
+        Some(CommandInput::Literal(_)) => Ok(Stdio::piped()),
+        None => Ok(Stdio::null())
+    }
+}
+
+/// Opens a regular file which has to be inside the checkout
+/// Influo may run with more privileges than the procedure, so the path of the opened file is checked rather than the configured one
+fn open_checkout_file(path: &Path, repository_path: &str) -> Result<File, Error> {
+    // Non-blocking so a fifo can't stall the procedure before it is rejected
+    let file: File = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+            .open(path)
+            .map_err(|e| anyhow!("Could not open input file {}: {}", path.display(), e))?;
+    if !file.metadata()?.is_file() {
+        return Err(anyhow!("Input file {} is not a regular file", path.display()));
+    }
+    let opened_path: PathBuf = std::fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd()))?;
+    if !opened_path.starts_with(std::fs::canonicalize(repository_path)?) {
+        return Err(anyhow!("Input file {} is outside the checkout", path.display()));
+    }
+    Ok(file)
+}
+
+/// Writes the literal input of a command in the background and closes its stdin afterwards
+pub fn write_literal_input(child: &mut Child, command: &ProcedureCommand) {
+    if let (Some(CommandInput::Literal(text)), Some(mut stdin)) = (&command.stdin, child.stdin.take()) {
+        let input: String = text.clone();
+        tokio::spawn(async move {
+            // The command may exit without reading its input
+            let _ = stdin.write_all(input.as_bytes()).await;
+        });
+    }
+}
+
+/// Creates the executor configured for a procedure
+/// Commands run as local processes unless configured otherwise
+pub fn create_executor(procedure: &Procedure, context: ExecutionContext) -> Box<dyn Executor> {
+    if let Some(target) = &procedure.target {
+        return Box::new(SshExecutor::new(context, target.clone()));
+    }
+    match &procedure.container {
+        Some(container) => Box::new(ContainerExecutor::new(context, container.clone(), procedure.limits.clone(), procedure.user.clone())),
+        None => Box::new(LocalExecutor::new(context, procedure.limits.clone(), procedure.user.clone())),
+    }
+}
@@ -0,0 +1,510 @@
+use std::{
+    process::Stdio,
+    sync::Arc,
+    time::Duration
+};
+use anyhow::{Error, anyhow};
+use futures::future::{BoxFuture, FutureExt, join_all};
+use tokio::{
+    process::Child,
+    sync::Mutex,
+    io::{AsyncWriteExt, duplex}
+};
+
+use crate::{
+    model::project::{
+        checkout::{CheckoutOptions, SubmoduleMode},
+        command::{ProcedureCommand, CommandInput},
+        target::RemoteTarget
+    },
+    system_cmd::run_with_timeout
+};
+use super::{
+    Executor, ExecutorProcess, ExecutionContext, ChildExit, OutputStream, ProcessSignal, write_literal_input,
+    lines::LineReader
+};
+
+/// Exit code of the ssh client when the connection failed instead of the remote command
+const SSH_CONNECTION_FAILURE: i32 = 255;
+/// Size of the buffer output of multiple hosts is merged in
+const MERGED_OUTPUT_BUFFER: usize = 64 * 1024;
+/// Seconds the ssh client has to connect to a host
+const CONNECT_TIMEOUT: u64 = 30;
+
+/// Runs commands on remote hosts using the system ssh client
+/// Every command runs on all hosts at once and only succeeds if it succeeded on each of them
+pub struct SshExecutor {
+    context: ExecutionContext,
+    target: RemoteTarget,
+    runs: u32, // Commands started by this run. Together with the run id keeps pid file names unique
+}
+
+impl SshExecutor {
+    pub fn new(context: ExecutionContext, target: RemoteTarget) -> SshExecutor {
+        SshExecutor {
+            context: context,
+            target: target,
+            runs: 0,
+        }
+    }
+}
+
+impl Executor for SshExecutor {
+    /// The repository is cloned by the remote hosts so they need access to the remote URL
+    fn checkout(&mut self, remote_url: &str, branch: &str, checkout: &CheckoutOptions) -> Result<(), Error> {
+        let script: String = checkout_script(remote_url, &self.context.deploy_path, &self.context.repository_path, branch, checkout);
+        for host in &self.target.hosts {
+            let mut ssh_command = std::process::Command::new("ssh");
+            ssh_command
+                    .args(ssh_args(&self.target, host, &script))
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::piped());
+            let failure: String = match run_with_timeout(&mut ssh_command, Some(checkout.timeout)) {
+                Ok(output) if output.status.success() => continue,
+                Ok(output) => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                Err(e) => e.to_string(),
+            };
+            debug!("Remote checkout on {} failed: {}", host, failure);
+            return Err(anyhow!("Failed to update/create git repository with URL: {} and branch: {} on host: {}", remote_url, branch, host));
+        }
+
+        Ok(())
+    }
+
+    fn spawn(&mut self, command: &ProcedureCommand) -> Result<Box<dyn ExecutorProcess>, Error> {
+        let pid_file: String = format!("{}/.influo-{}-{}-{}.pid", self.context.deploy_path, self.context.run_name, self.context.run_id, self.runs);
+        self.runs += 1;
+
+        let script: String = spawn_script(&self.context, command, &pid_file)?;
+
+        let mut hosts: Vec<HostProcess> = Vec::new();
+        for host in &self.target.hosts {
+            let input: Stdio = match command.stdin {
+                Some(CommandInput::Literal(_)) => Stdio::piped(),
+                _ => Stdio::null()
+            };
+            let mut child: Child = tokio::process::Command::new("ssh")
+                    .args(ssh_args(&self.target, host, &script))
+                    .stdin(input)
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .kill_on_drop(true)
+                    .spawn()?;
+            write_literal_input(&mut child, command);
+            hosts.push(HostProcess {
+                host: host.clone(),
+                child: child,
+            });
+        }
+
+        Ok(Box::new(SshProcess {
+            target: self.target.clone(),
+            pid_file: pid_file,
+            hosts: hosts,
+            max_line_length: self.context.max_line_length,
+            line_flush_timeout: self.context.line_flush_timeout,
+        }))
+    }
+}
+
+struct HostProcess {
+    host: String,
+    child: Child, // The ssh client
+}
+
+/// A command running on every host of the target
+pub struct SshProcess {
+    target: RemoteTarget,
+    pid_file: String,
+    hosts: Vec<HostProcess>,
+    max_line_length: usize,
+    line_flush_timeout: Duration,
+}
+
+impl ExecutorProcess for SshProcess {
+    fn stdout(&mut self) -> Option<OutputStream> {
+        let streams: Vec<(String, OutputStream)> = self.hosts.iter_mut()
+            .filter_map(|h| h.child.stdout.take().map(|s| (h.host.clone(), Box::new(s) as OutputStream)))
+            .collect();
+        merge_host_output(streams, self.max_line_length, self.line_flush_timeout)
+    }
+
+    fn stderr(&mut self) -> Option<OutputStream> {
+        let streams: Vec<(String, OutputStream)> = self.hosts.iter_mut()
+            .filter_map(|h| h.child.stderr.take().map(|s| (h.host.clone(), Box::new(s) as OutputStream)))
+            .collect();
+        merge_host_output(streams, self.max_line_length, self.line_flush_timeout)
+    }
+
+    /// Resolves once the command exited on every host with the exit of the first host it failed on
+    fn wait(&mut self) -> BoxFuture<'_, ChildExit> {
+        async move {
+            let statuses = join_all(self.hosts.iter_mut().map(|h| h.child.wait())).await;
+            let mut result = ChildExit {
+                success: true,
+                code: 0,
+                signal: None,
+                oom_killed: false,
+            };
+            for (host, status) in self.hosts.iter().zip(statuses) {
+                let exit = ChildExit::from_status(status);
+                if exit.code == SSH_CONNECTION_FAILURE {
+                    warn!("Connection to {} failed or was lost", host.host);
+                }
+                if !exit.success && result.success {
+                    result = exit;
+                }
+            }
+            result
+        }.boxed()
+    }
+
+    /// Signals are sent over a new connection since killing the ssh client alone would leave the command running
+    fn signal(&mut self, signal: ProcessSignal) -> Result<(), Error> {
+        let remote_signal: &str = match signal {
+            ProcessSignal::Terminate => "TERM",
+            ProcessSignal::Kill => "KILL",
+        };
+        let script: String = format!("test -f {0} && kill -s {1} \"$(cat {0})\"", shell_words::quote(&self.pid_file), remote_signal);
+        let mut failed_hosts: Vec<&str> = Vec::new();
+        for host in &mut self.hosts {
+            let status = std::process::Command::new("ssh")
+                    .args(ssh_args(&self.target, &host.host, &script))
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status();
+            if signal == ProcessSignal::Kill {
+                let _ = host.child.start_kill(); // The client may already have exited with the command
+            }
+            match status {
+                Ok(s) if s.success() => (),
+                _ => failed_hosts.push(&host.host),
+            }
+        }
+        if !failed_hosts.is_empty() {
+            return Err(anyhow!("Unable to signal command on {}. It may already be stopped.", failed_hosts.join(", ")));
+        }
+
+        Ok(())
+    }
+}
+
+/// Arguments for running a script on a host using sh regardless of the login shell of the user
+fn ssh_args(target: &RemoteTarget, host: &str, script: &str) -> Vec<String> {
+    let mut args: Vec<String> = vec![
+        "-o".to_string(), "BatchMode=yes".to_string(), // Fail instead of prompting for passwords
+    ];
+    if let Some(port) = target.port {
+        args.push("-p".to_string());
+        args.push(port.to_string());
+    }
+    if let Some(identity_file) = &target.identity_file {
+        args.push("-i".to_string());
+        args.push(identity_file.clone());
+    }
+    args.extend(target.options.iter().cloned());
+    // After the configured options since ssh uses the first value of an option
+    args.push("-o".to_string());
+    args.push(format!("ConnectTimeout={}", CONNECT_TIMEOUT));
+    args.push(host.to_string());
+    args.push("--".to_string());
+    args.push(format!("sh -c {}", shell_words::quote(script)));
+    args
+}
+
+/// Script running a command on a remote host and keeping its pid in the pid file while it runs
+/// The command runs in the background of a wrapper shell so it can be signalled using its pid
+fn spawn_script(context: &ExecutionContext, command: &ProcedureCommand, pid_file: &str) -> Result<String, Error> {
+    let mut script: Vec<String> = vec![format!("cd {} || exit 1", shell_words::quote(&context.working_directory(command)))];
+    for (key, value) in context.command_environment(command) {
+        script.push(format!("export {}={}", key, shell_words::quote(&value)));
+    }
+    // Background commands read from /dev/null unless their input is redirected explicitly
+    // Input files are read on the remote host like the rest of the checkout
+    let input_redirect: String = match &command.stdin {
+        Some(CommandInput::File(file)) => format!(" < {}", shell_words::quote(file)),
+        Some(CommandInput::Literal(_)) => {
+            script.push("exec 3<&0".to_string());
+            " <&3".to_string()
+        },
+        None => String::new()
+    };
+    script.push(format!("{}{} &", shell_words::join(context.shell.command_line(&command.command)?), input_redirect));
+    script.push(format!("echo $! > {}", shell_words::quote(pid_file)));
+    script.push("wait $!".to_string());
+    script.push("status=$?".to_string());
+    script.push(format!("rm -f {}", shell_words::quote(pid_file)));
+    script.push("exit $status".to_string());
+    Ok(script.join("\n"))
+}
+
+/// Script cloning or updating the repository on a remote host
+/// Mirrors the local checkout but resets to the fetched branch since nobody should modify deployments
+fn checkout_script(remote_url: &str, deploy_path: &str, repository_path: &str, branch: &str, checkout: &CheckoutOptions) -> String {
+    let depth_flag: String = match checkout.depth {
+        Some(depth) => format!(" --depth {}", depth),
+        None => String::new()
+    };
+    let sparse_flag: &str = if checkout.sparse_paths.is_empty() { "" } else { " --sparse" };
+    let repository_path = shell_words::quote(repository_path);
+    let branch = shell_words::quote(branch);
+
+    let mut script: Vec<String> = vec![
+        "set -e".to_string(),
+        format!("mkdir -p {}", shell_words::quote(deploy_path)),
+        format!("if [ -d {}/.git ]; then", repository_path),
+        format!("cd {}", repository_path),
+        format!("git fetch{} origin {}", depth_flag, branch),
+        "git reset --hard FETCH_HEAD".to_string(),
+        "else".to_string(),
+        format!("git clone --single-branch{}{} --branch {} {} {}", depth_flag, sparse_flag, branch, shell_words::quote(remote_url), repository_path),
+        format!("cd {}", repository_path),
+        "fi".to_string(),
+    ];
+
+    if checkout.sparse_paths.is_empty() {
+        script.push("if [ \"$(git config --get core.sparseCheckout || true)\" = true ]; then git sparse-checkout disable; fi".to_string());
+    } else {
+        script.push("git sparse-checkout init --cone".to_string());
+        script.push(format!("git sparse-checkout set {}", shell_words::join(&checkout.sparse_paths)));
+    }
+    if checkout.submodules != SubmoduleMode::Disabled {
+        let recursive_flag: &str = if checkout.submodules == SubmoduleMode::Recursive { " --recursive" } else { "" };
+        script.push(format!("git submodule sync{}", recursive_flag));
+        script.push(format!("git submodule update --init --force{}", recursive_flag));
+    }
+    if checkout.lfs {
+        script.push("git lfs install --local".to_string());
+        script.push("git lfs pull".to_string());
+    }
+
+    script.join("\n")
+}
+
+/// Merges the output of all hosts line by line
+/// Lines are prefixed with their host once there is more than one
+fn merge_host_output(mut streams: Vec<(String, OutputStream)>, max_line_length: usize, line_flush_timeout: Duration) -> Option<OutputStream> {
+    if streams.len() <= 1 {
+        return streams.pop().map(|(_, s)| s);
+    }
+
+    let (reader, writer) = duplex(MERGED_OUTPUT_BUFFER);
+    let writer = Arc::new(Mutex::new(writer));
+    for (host, stream) in streams {
+        let host_writer = Arc::clone(&writer);
+        tokio::spawn(async move {
+            // Room is left for the prefix so merged lines aren't split again by the reader of the procedure
+            let mut lines = LineReader::new(stream, max_line_length.saturating_sub(host.len() + 3), line_flush_timeout);
+            while let Some(line) = lines.next_line().await {
+                let mut w = host_writer.lock().await;
+                if w.write_all(format!("[{}] {}\n", host, line).as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    Some(Box::new(reader))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::Write,
+        path::{Path, PathBuf},
+        process::{Command, Output}
+    };
+    use serde_json::json;
+    use tokio::io::AsyncReadExt;
+    use super::*;
+    use crate::model::project::{checkout::SubmoduleMode, shell::Shell};
+
+    /// Empty directory whose path needs quoting in scripts
+    fn test_directory(name: &str) -> PathBuf {
+        let directory: PathBuf = std::env::temp_dir().join(format!("influo ssh-{}-{}'s dir", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn test_context(repository_path: &Path) -> ExecutionContext {
+        ExecutionContext {
+            procedure_name: "deploy".to_string(),
+            run_name: "repo-main-deploy".to_string(),
+            run_id: "20260101000000-0".to_string(),
+            deploy_path: repository_path.parent().unwrap().to_string_lossy().into_owned(),
+            repository_path: repository_path.to_string_lossy().into_owned(),
+            shell: Shell::Interpreter("sh".to_string(), vec!["-c".to_string()]),
+            environment: vec![("INFLUO_PROCEDURE".to_string(), "deploy".to_string())],
+            max_line_length: 64,
+            line_flush_timeout: Duration::from_millis(100),
+        }
+    }
+
+    /// Runs a script the way the remote host does
+    fn run_script(script: &str, directory: &Path, input: &str) -> Output {
+        let mut child = Command::new("sh")
+                .arg("-c")
+                .arg(script)
+                .current_dir(directory)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap();
+        child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    fn git(directory: &Path, args: &[&str]) {
+        let status = Command::new("git")
+                .args(["-c", "user.name=Influo", "-c", "user.email=influo@localhost", "-c", "init.defaultBranch=main"])
+                .args(args)
+                .current_dir(directory)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .unwrap();
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    #[test]
+    fn spawn_script_quotes_environment_and_paths() {
+        let directory: PathBuf = test_directory("spawn");
+        let pid_file: PathBuf = directory.join("run $(id).pid");
+        let command = ProcedureCommand::new(&json!({
+            "run": "printf '%s|%s\\n' \"$VALUE\" \"$INFLUO_PROCEDURE\"; pwd",
+            "env": { "VALUE": "it's $(echo injected) `echo x` \"quoted\"" },
+        }), false).unwrap();
+
+        let script: String = spawn_script(&test_context(&directory), &command, &pid_file.to_string_lossy()).unwrap();
+        let output: Output = run_script(&script, &directory, "");
+
+        assert!(output.status.success());
+        let stdout: String = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(stdout, format!("it's $(echo injected) `echo x` \"quoted\"|deploy\n{}\n", directory.display()));
+        assert!(!pid_file.exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn spawn_script_redirects_input() {
+        let directory: PathBuf = test_directory("input");
+        fs::write(directory.join("input file's.txt"), "from file\n").unwrap();
+        let context: ExecutionContext = test_context(&directory);
+        let pid_file: String = directory.join("run.pid").to_string_lossy().into_owned();
+
+        let file_command = ProcedureCommand::new(&json!({ "run": "cat", "stdin": { "file": "input file's.txt" } }), false).unwrap();
+        let output: Output = run_script(&spawn_script(&context, &file_command, &pid_file).unwrap(), &directory, "ignored\n");
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "from file\n");
+
+        let literal_command = ProcedureCommand::new(&json!({ "run": "cat", "stdin": "literal" }), false).unwrap();
+        let output: Output = run_script(&spawn_script(&context, &literal_command, &pid_file).unwrap(), &directory, "literal\n");
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "literal\n");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn spawn_script_keeps_exit_status() {
+        let directory: PathBuf = test_directory("status");
+        let command = ProcedureCommand::new(&json!({ "run": "exit 3" }), false).unwrap();
+        let script: String = spawn_script(&test_context(&directory), &command, &directory.join("run.pid").to_string_lossy()).unwrap();
+        assert_eq!(run_script(&script, &directory, "").status.code(), Some(3));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn checkout_script_clones_and_updates() {
+        let directory: PathBuf = test_directory("checkout");
+        let origin: PathBuf = directory.join("origin repo");
+        fs::create_dir_all(&origin).unwrap();
+        git(&origin, &["init", "-q"]);
+        fs::write(origin.join("version"), "1\n").unwrap();
+        git(&origin, &["add", "version"]);
+        git(&origin, &["commit", "-q", "-m", "first"]);
+        let branch: &str = "feature/it's-$(touch${IFS}pwned)";
+        git(&origin, &["checkout", "-q", "-b", branch]);
+
+        let deploy_path: PathBuf = directory.join("deploy; rm -rf x");
+        let repository_path: PathBuf = deploy_path.join("checkout");
+        let checkout = CheckoutOptions {
+            submodules: SubmoduleMode::Disabled,
+            lfs: false,
+            depth: None,
+            sparse_paths: Vec::new(),
+            timeout: Duration::from_secs(60),
+        };
+        let script: String = checkout_script(&origin.to_string_lossy(), &deploy_path.to_string_lossy(), &repository_path.to_string_lossy(), branch, &checkout);
+
+        let output: Output = run_script(&script, &directory, "");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(fs::read_to_string(repository_path.join("version")).unwrap(), "1\n");
+
+        fs::write(origin.join("version"), "2\n").unwrap();
+        git(&origin, &["commit", "-q", "-a", "-m", "second"]);
+        let output: Output = run_script(&script, &directory, "");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(fs::read_to_string(repository_path.join("version")).unwrap(), "2\n");
+
+        assert!(!directory.join("pwned").exists() && !repository_path.join("pwned").exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn ssh_args_pass_script_as_one_word() {
+        let target = RemoteTarget {
+            hosts: vec!["deploy@example.com".to_string()],
+            port: Some(2222),
+            identity_file: Some("/keys/deploy key".to_string()),
+            options: vec!["-o".to_string(), "ConnectTimeout=5".to_string()],
+        };
+        let script: &str = "cd '/srv/app' || exit 1\necho \"$HOME\" 'single' $(date)";
+        let args: Vec<String> = ssh_args(&target, "deploy@example.com", script);
+
+        assert_eq!(args[..11], [
+            "-o", "BatchMode=yes",
+            "-p", "2222",
+            "-i", "/keys/deploy key",
+            "-o", "ConnectTimeout=5",
+            "-o", "ConnectTimeout=30",
+            "deploy@example.com",
+        ]);
+        assert_eq!(args[11], "--");
+        assert_eq!(args.len(), 13);
+        // The remote login shell splits the command again
+        assert_eq!(shell_words::split(&args[12]).unwrap(), ["sh", "-c", script]);
+    }
+
+    fn read_merged(streams: Vec<(&str, &'static str)>, max_line_length: usize) -> Vec<String> {
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let streams: Vec<(String, OutputStream)> = streams.into_iter()
+                .map(|(host, output)| (host.to_string(), Box::new(output.as_bytes()) as OutputStream))
+                .collect();
+            let mut merged: OutputStream = merge_host_output(streams, max_line_length, Duration::from_millis(100)).unwrap();
+            let mut output: String = String::new();
+            merged.read_to_string(&mut output).await.unwrap();
+            output.lines().map(|l| l.to_string()).collect()
+        })
+    }
+
+    #[test]
+    fn merge_host_output_prefixes_lines_of_multiple_hosts() {
+        let mut lines: Vec<String> = read_merged(vec![("web1", "first\nsecond\n"), ("web2", "third")], 64);
+        lines.sort();
+        assert_eq!(lines, ["[web1] first", "[web1] second", "[web2] third"]);
+
+        assert_eq!(read_merged(vec![("web1", "only host\n")], 64), ["only host"]);
+    }
+
+    #[test]
+    fn merge_host_output_leaves_room_for_the_prefix() {
+        let long_line: &'static str = Box::leak("x".repeat(150).into_boxed_str());
+        let lines: Vec<String> = read_merged(vec![("web1", long_line), ("web2", "")], 64);
+        assert!(lines.iter().all(|l| l.len() <= 64 && l.starts_with("[web1] ")));
+        assert_eq!(lines.iter().map(|l| l.len() - "[web1] ".len()).sum::<usize>(), 150);
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex
+};
+use anyhow::{Error, anyhow};
+use chrono::{DateTime, Utc, SecondsFormat};
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+
+lazy_static! {
+    // Set once the history is enabled. Runs are appended one at a time
+    static ref HISTORY_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+/// File inside the data path every finished procedure run is appended to as a JSON line
+pub const HISTORY_FILE: &str = "history.jsonl";
+/// Runs returned by a query unless a limit is given
+pub const DEFAULT_QUERY_LIMIT: usize = 50;
+
+/// What started a procedure run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    Poll, // A new commit was found while polling the remote
+}
+
+impl Trigger {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Trigger::Poll => "poll",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunStatus {
+    Succeeded,
+    Failed,
+    Stopped, // A newer commit or shutdown stopped the run
+}
+
+impl RunStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RunStatus::Succeeded => "succeeded",
+            RunStatus::Failed => "failed",
+            RunStatus::Stopped => "stopped",
+        }
+    }
+}
+
+/// Result of one attempt of a command
+#[derive(Debug, Clone)]
+pub struct CommandRecord {
+    pub command: String,
+    pub exit_code: Option<i32>, // None if the command didn't exit on its own
+    pub result: &'static str, // succeeded, failed, timed_out, stopped or not_started
+}
+
+#[derive(Debug, Clone)]
+pub struct RunRecord {
+    pub run_id: String,
+    pub project_url: String,
+    pub branch: String,
+    pub commit: String,
+    pub procedure: String,
+    pub trigger: Trigger,
+    pub started_at: DateTime<Utc>,
+    pub commands: Vec<CommandRecord>, // Every attempt in the order they ran
+    pub restarts: u32, // Retries of build steps and restarts of services
+    pub status: RunStatus,
+    pub error: Option<String>, // Why the run failed
+}
+
+impl RunRecord {
+    pub fn new(run_id: &str, project_url: &str, branch: &str, commit: &str, procedure: &str, trigger: Trigger) -> RunRecord {
+        RunRecord {
+            run_id: run_id.to_string(),
+            project_url: project_url.to_string(),
+            branch: branch.to_string(),
+            commit: commit.to_string(),
+            procedure: procedure.to_string(),
+            trigger: trigger,
+            started_at: Utc::now(),
+            commands: Vec::new(),
+            restarts: 0,
+            status: RunStatus::Failed,
+            error: None,
+        }
+    }
+
+    pub fn record_command(&mut self, command: &str, exit_code: Option<i32>, result: &'static str) {
+        self.commands.push(CommandRecord {
+            command: command.to_string(),
+            exit_code: exit_code,
+            result: result,
+        });
+    }
+
+    fn to_json(&self, finished_at: DateTime<Utc>) -> Value {
+        let commands: Vec<Value> = self.commands.iter().map(|c| json!({
+            "command": c.command,
+            "exit_code": c.exit_code,
+            "result": c.result,
+        })).collect();
+        json!({
+            "run_id": self.run_id,
+            "project_url": self.project_url,
+            "branch": self.branch,
+            "commit": self.commit,
+            "procedure": self.procedure,
+            "trigger": self.trigger.as_str(),
+            "started_at": self.started_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "finished_at": finished_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "commands": commands,
+            "restarts": self.restarts,
+            "status": self.status.as_str(),
+            "error": self.error,
+        })
+    }
+}
+
+/// Selects runs by their fields. Unset fields match every run
+#[derive(Debug, Clone)]
+pub struct HistoryFilter {
+    pub project_url: Option<String>,
+    pub branch: Option<String>,
+    pub procedure: Option<String>,
+    pub status: Option<String>,
+    pub limit: usize, // At least 1
+}
+
+impl HistoryFilter {
+    fn matches(&self, run: &Value) -> bool {
+        let fields = [
+            ("project_url", &self.project_url),
+            ("branch", &self.branch),
+            ("procedure", &self.procedure),
+            ("status", &self.status),
+        ];
+        fields.iter().all(|(key, value)| match value {
+            Some(v) => run[*key].as_str() == Some(v.as_str()),
+            None => true
+        })
+    }
+}
+
+/// Starts recording runs in the data path
+pub fn enable(data_path: &str) -> Result<(), Error> {
+    fs::create_dir_all(data_path).map_err(|e| anyhow!("Unable to create data path {}: {}", data_path, e))?;
+    *HISTORY_PATH.lock().unwrap() = Some(Path::new(data_path).join(HISTORY_FILE));
+    Ok(())
+}
+
+/// Appends a finished run to the history if it is enabled
+pub fn append(record: &RunRecord) {
+    let history_path = HISTORY_PATH.lock().unwrap();
+    let path: &PathBuf = match history_path.as_ref() {
+        Some(p) => p,
+        None => return,
+    };
+    let mut line: String = record.to_json(Utc::now()).to_string();
+    line.push('\n');
+    let result = OpenOptions::new().create(true).append(true).open(path)
+        .and_then(|mut f| f.write_all(line.as_bytes()));
+    if let Err(e) = result {
+        warn!("[{}] Failed to record run in history file {}: {}", record.procedure, path.display(), e);
+    }
+}
+
+/// Queries the history Influo is recording to
+pub fn query_enabled(filter: &HistoryFilter) -> Result<Vec<Value>, Error> {
+    let path: PathBuf = match HISTORY_PATH.lock().unwrap().as_ref() {
+        Some(p) => p.clone(),
+        None => return Err(anyhow!("Run history is disabled")),
+    };
+    query(&path, filter)
+}
+
+/// Commit of the last successful run of a procedure. None if history is disabled or it never succeeded
+/// Reads the whole history file, which grows without bound. It is only called for new commits of procedures with path filters
+pub fn last_succeeded_commit(project_url: &str, branch: &str, procedure: &str) -> Option<String> {
+    let filter = HistoryFilter {
+        project_url: Some(project_url.to_string()),
+        branch: Some(branch.to_string()),
+        procedure: Some(procedure.to_string()),
+        status: Some(RunStatus::Succeeded.as_str().to_string()),
+        limit: 1,
+    };
+    match query_enabled(&filter) {
+        Ok(runs) => runs.first().and_then(|r| r["commit"].as_str()).map(|c| c.to_string()),
+        Err(_) => None
+    }
+}
+
+/// Returns the latest runs matching the filter, newest first
+/// Lines which can't be parsed such as a line cut off by a crash are skipped
+pub fn query(path: &Path, filter: &HistoryFilter) -> Result<Vec<Value>, Error> {
+    let file: File = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(anyhow!("Unable to read history file {}: {}", path.display(), e)),
+    };
+    let mut runs: VecDeque<Value> = VecDeque::new();
+    for line in BufReader::new(file).lines() {
+        let run: Value = match line.ok().and_then(|l| serde_json::from_str(&l).ok()) {
+            Some(r) => r,
+            None => continue,
+        };
+        if !filter.matches(&run) {
+            continue;
+        }
+        if runs.len() == filter.limit {
+            runs.pop_front();
+        }
+        runs.push_back(run);
+    }
+
+    Ok(runs.into_iter().rev().collect())
+}
+
+/// influo history [--project URL] [--branch NAME] [--procedure NAME] [--status STATUS] [--limit N] [--json]
+pub fn run_cli(args: &[String], data_path: &str) -> Result<(), Error> {
+    let mut filter = HistoryFilter {
+        project_url: None,
+        branch: None,
+        procedure: None,
+        status: None,
+        limit: DEFAULT_QUERY_LIMIT,
+    };
+    let mut json_output = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or_else(|| anyhow!("{} needs a value", arg));
+        match arg.as_str() {
+            "--project" => filter.project_url = Some(value()?),
+            "--branch" => filter.branch = Some(value()?),
+            "--procedure" => filter.procedure = Some(value()?),
+            "--status" => filter.status = Some(value()?),
+            "--limit" => filter.limit = match value()?.parse() {
+                Ok(l) if l > 0 => l,
+                _ => return Err(anyhow!("--limit must be a positive number")),
+            },
+            "--json" => json_output = true,
+            _ => return Err(anyhow!("Unknown argument {}. Usage: influo history [--project URL] [--branch NAME] [--procedure NAME] [--status STATUS] [--limit N] [--json]", arg)),
+        }
+    }
+
+    let runs: Vec<Value> = query(&Path::new(data_path).join(HISTORY_FILE), &filter)?;
+    if json_output {
+        for run in &runs {
+            println!("{}", run);
+        }
+        return Ok(());
+    }
+
+    println!("{:<20}  {:>8}  {:<9}  {:>8}  {:<7}  {:<16}  {:<16}  PROJECT", "STARTED", "DURATION", "STATUS", "RESTARTS", "COMMIT", "PROCEDURE", "BRANCH");
+    for run in &runs {
+        let started_at: Option<DateTime<Utc>> = run["started_at"].as_str().and_then(|s| s.parse().ok());
+        let finished_at: Option<DateTime<Utc>> = run["finished_at"].as_str().and_then(|s| s.parse().ok());
+        let duration: String = match (started_at, finished_at) {
+            (Some(s), Some(f)) => format!("{}s", (f - s).num_seconds()),
+            _ => "-".to_string()
+        };
+        let commit: &str = run["commit"].as_str().unwrap_or("-");
+        println!("{:<20}  {:>8}  {:<9}  {:>8}  {:<7}  {:<16}  {:<16}  {}",
+            started_at.map_or("-".to_string(), |s| s.format("%Y-%m-%d %H:%M:%S").to_string()),
+            duration,
+            run["status"].as_str().unwrap_or("-"),
+            run["restarts"].as_u64().unwrap_or(0),
+            commit.get(..7).unwrap_or(commit),
+            run["procedure"].as_str().unwrap_or("-"),
+            run["branch"].as_str().unwrap_or("-"),
+            run["project_url"].as_str().unwrap_or("-"));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use std::fs;
+use anyhow::Error;
+
+use crate::model::project::limits::ResourceLimits;
+
+/// A cgroup v2 sub-tree containing every process of a procedure
+#[derive(Debug)]
+pub struct Cgroup {
+    pub path: String,
+}
+
+impl Cgroup {
+    /// Creates the sub-tree inside a delegated cgroup and writes the limits to its controllers
+    pub fn create(root: &str, name: &str, limits: &ResourceLimits) -> Result<Cgroup, Error> {
+        // Controllers may already be enabled and unavailable ones shouldn't prevent the others
+        for controller in &["+memory", "+cpu", "+pids"] {
+            let _ = fs::write(format!("{}/cgroup.subtree_control", root), controller);
+        }
+
+        let cgroup = Cgroup {
+            path: format!("{}/{}", root, name),
+        };
+        fs::create_dir_all(&cgroup.path)?;
+        if let Err(e) = cgroup.write_limits(limits) {
+            cgroup.remove();
+            return Err(e);
+        }
+
+        Ok(cgroup)
+    }
+
+    fn write_limits(&self, limits: &ResourceLimits) -> Result<(), Error> {
+        if let Some(memory) = limits.memory {
+            fs::write(format!("{}/memory.max", self.path), memory.to_string())?;
+            let _ = fs::write(format!("{}/memory.swap.max", self.path), "0"); // Swap accounting may be disabled
+        }
+        if let Some(cpu_weight) = limits.cpu_weight {
+            fs::write(format!("{}/cpu.weight", self.path), cpu_weight.to_string())?;
+        }
+        if let Some(processes) = limits.processes {
+            fs::write(format!("{}/pids.max", self.path), processes.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    /// Number of processes killed by the OOM killer inside the cgroup
+    pub fn oom_kills(&self) -> u64 {
+        let events: String = match fs::read_to_string(format!("{}/memory.events", self.path)) {
+            Ok(s) => s,
+            Err(_) => return 0,
+        };
+        events.lines()
+            .filter_map(|l| l.strip_prefix("oom_kill "))
+            .filter_map(|n| n.trim().parse::<u64>().ok())
+            .next()
+            .unwrap_or(0)
+    }
+
+    /// Removes the sub-tree which only succeeds once every process in it exited
+    pub fn remove(&self) {
+        if let Err(e) = fs::remove_dir(&self.path) {
+            debug!("Unable to remove cgroup {}: {}", self.path, e);
+        }
+    }
+}
+
+/// Applies the limits to a command before it is spawned
+/// The child joins the cgroup and sets its rlimits and priority before executing the program
+#[cfg(unix)]
+pub fn apply_process_limits(command: &mut tokio::process::Command, limits: &ResourceLimits, cgroup: Option<&Cgroup>) -> Result<(), Error> {
+    use std::{io, ffi::CString};
+
+    if limits.is_empty() {
+        return Ok(());
+    }
+
+    let cgroup_procs_path: Option<CString> = match cgroup {
+        Some(c) => Some(CString::new(format!("{}/cgroup.procs", c.path))?),
+        None => None
+    };
+    // Without a cgroup the memory limit falls back to limiting the address space
+    let address_space_limit: Option<u64> = if cgroup.is_none() { limits.memory } else { None };
+    let open_files: Option<u64> = limits.open_files;
+    // pids.max only counts the procedure's processes while RLIMIT_NPROC counts every process of the user
+    let processes: Option<u64> = if cgroup.is_none() { limits.processes } else { None };
+    let nice: Option<i32> = limits.nice;
+
+    // Only async-signal-safe calls are allowed between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(path) = &cgroup_procs_path {
+                let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let written = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1); // 0 is the writing process
+                libc::close(fd);
+                if written != 1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            let rlimits = [
+                (libc::RLIMIT_AS, address_space_limit),
+                (libc::RLIMIT_NOFILE, open_files),
+                (libc::RLIMIT_NPROC, processes),
+            ];
+            for (resource, value) in rlimits.iter() {
+                if let Some(v) = value {
+                    let limit = libc::rlimit {
+                        rlim_cur: *v as libc::rlim_t,
+                        rlim_max: *v as libc::rlim_t,
+                    };
+                    if libc::setrlimit(*resource, &limit) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+            }
+
+            if let Some(n) = nice {
+                if libc::setpriority(libc::PRIO_PROCESS, 0, n) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            Ok(())
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn apply_process_limits(_command: &mut tokio::process::Command, _limits: &ResourceLimits, _cgroup: Option<&Cgroup>) -> Result<(), Error> {
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::SystemTime
+};
+use anyhow::Error;
+use chrono::{Utc, SecondsFormat};
+use flate2::{Compression, write::GzEncoder};
+use lazy_static::lazy_static;
+
+use crate::{
+    model::project::log_files::LogFileOptions,
+    system_cmd::sanitize_path_component
+};
+
+lazy_static! {
+    // Log files in use and the number of runs writing to them. Branches on the same commit share a file
+    static ref OPEN_LOGS: Mutex<HashMap<PathBuf, usize>> = Mutex::new(HashMap::new());
+}
+
+/// Log file of a procedure run at {directory}/{project}/{procedure}/{commit}.log
+/// Rotated parts of the file are named {commit}.{n}.log where a higher n is newer
+pub struct ProcedureLog {
+    options: LogFileOptions,
+    directory: PathBuf,
+    commit: String,
+    file: File,
+    size: u64,
+}
+
+impl ProcedureLog {
+    /// Opens the log file of a commit and cleans up files of previous runs
+    /// Runs of the same commit append to the same file
+    pub fn open(options: &LogFileOptions, project_url: &str, procedure_name: &str, commit: &str) -> Result<ProcedureLog, Error> {
+        let directory: PathBuf = Path::new(&options.directory)
+            .join(sanitize_path_component(project_url))
+            .join(sanitize_path_component(procedure_name));
+        fs::create_dir_all(&directory)?;
+
+        let path: PathBuf = directory.join(format!("{}.log", commit));
+        let file: File = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size: u64 = file.metadata()?.len();
+        register(&path);
+
+        // Cleaned up in the background so compressing large files doesn't delay the run
+        let (clean_up_options, clean_up_directory) = (options.clone(), directory.clone());
+        thread::spawn(move || clean_up(&clean_up_options, &clean_up_directory));
+
+        Ok(ProcedureLog {
+            options: options.clone(),
+            directory: directory,
+            commit: commit.to_string(),
+            file: file,
+            size: size,
+        })
+    }
+
+    /// Appends a line of a stream such as stdout to the log
+    pub fn write_line(&mut self, stream: &str, line: &str) {
+        let record: String = format!("{} [{}] {}\n", Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true), stream, line);
+        if let Some(max_size) = self.options.max_size {
+            if self.size > 0 && self.size + record.len() as u64 > max_size {
+                if let Err(e) = self.rotate() {
+                    warn!("Failed to rotate log file in {}: {}", self.directory.display(), e);
+                }
+            }
+        }
+        match self.file.write_all(record.as_bytes()) {
+            Ok(()) => self.size += record.len() as u64,
+            Err(e) => debug!("Failed to write to log file in {}: {}", self.directory.display(), e),
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.directory.join(format!("{}.log", self.commit))
+    }
+
+    /// Moves the current file to the next free part number and starts a new file
+    fn rotate(&mut self) -> Result<(), Error> {
+        let mut part: u32 = 1;
+        let part_path: PathBuf = loop {
+            let candidate: PathBuf = self.directory.join(format!("{}.{}.log", self.commit, part));
+            if !candidate.exists() && !gzip_path(&candidate).exists() {
+                break candidate;
+            }
+            part += 1;
+        };
+
+        fs::rename(self.path(), &part_path)?;
+        self.file = OpenOptions::new().create(true).append(true).open(self.path())?;
+        self.size = 0;
+        if self.options.compress {
+            // Compressed in the background since lines are written while the output of the procedure is read
+            // The part stays registered meanwhile so it isn't compressed by a clean up at the same time
+            register(&part_path);
+            thread::spawn(move || {
+                if let Err(e) = compress_file(&part_path) {
+                    warn!("Failed to compress log file {}: {}", part_path.display(), e);
+                }
+                unregister(&part_path);
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for ProcedureLog {
+    fn drop(&mut self) {
+        unregister(&self.path());
+    }
+}
+
+/// Deletes files older than the max age and compresses the remaining logs of other runs
+/// Files which are still being written by a run of any branch are left alone until the run ended
+fn clean_up(options: &LogFileOptions, directory: &Path) {
+    let entries = match fs::read_dir(directory) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path: PathBuf = entry.path();
+        if is_open(&path) {
+            continue;
+        }
+
+        let expired: bool = match (options.max_age, entry.metadata().and_then(|m| m.modified())) {
+            (Some(max_age), Ok(modified)) => SystemTime::now().duration_since(modified).is_ok_and(|age| age > max_age),
+            _ => false
+        };
+        let result: io::Result<()> = if expired {
+            remove_unless_open(&path)
+        } else if options.compress && path.extension().is_some_and(|e| e == "log") {
+            compress_unless_open(&path)
+        } else {
+            Ok(())
+        };
+        if let Err(e) = result {
+            debug!("Failed to clean up log file {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn register(path: &Path) {
+    *OPEN_LOGS.lock().unwrap().entry(path.to_path_buf()).or_insert(0) += 1;
+}
+
+fn unregister(path: &Path) {
+    let mut open_logs = OPEN_LOGS.lock().unwrap();
+    if let Some(count) = open_logs.get_mut(path) {
+        *count -= 1;
+        if *count == 0 {
+            open_logs.remove(path);
+        }
+    }
+}
+
+fn is_open(path: &Path) -> bool {
+    OPEN_LOGS.lock().unwrap().contains_key(path)
+}
+
+/// Holds the registry while removing so a run can't open the file in between
+fn remove_unless_open(path: &Path) -> io::Result<()> {
+    let open_logs = OPEN_LOGS.lock().unwrap();
+    if open_logs.contains_key(path) {
+        return Ok(());
+    }
+    fs::remove_file(path)
+}
+
+fn compress_unless_open(path: &Path) -> io::Result<()> {
+    write_gzip(path)?;
+    let open_logs = OPEN_LOGS.lock().unwrap();
+    if open_logs.contains_key(path) {
+        // Opened by a run while it was compressed, so the copy may already be incomplete
+        return fs::remove_file(gzip_path(path));
+    }
+    fs::remove_file(path)
+}
+
+fn gzip_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".gz");
+    path.with_file_name(file_name)
+}
+
+/// Replaces a file with a gzip compressed copy
+fn compress_file(path: &Path) -> io::Result<()> {
+    write_gzip(path)?;
+    fs::remove_file(path)
+}
+
+fn write_gzip(path: &Path) -> io::Result<()> {
+    let mut input: File = File::open(path)?;
+    let mut encoder = GzEncoder::new(File::create(gzip_path(path))?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
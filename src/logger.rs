@@ -1,100 +1,174 @@
-use lazy_static::lazy_static;
-use std::sync::Mutex;
+use std::{
+    fmt,
+    fs::OpenOptions,
+    io::{self, Write},
+    sync::Mutex
+};
+use anyhow::{Error, anyhow};
+use chrono::{Utc, SecondsFormat};
+use serde_json::{Map, Value};
+use tracing::{Event, Subscriber, Level, field::{Field, Visit}, span::{Attributes, Id, Record}};
+use tracing_subscriber::{
+    EnvFilter,
+    Layer,
+    Registry,
+    prelude::*,
+    fmt::writer::{BoxMakeWriter, MakeWriter},
+    layer::Context,
+    registry::LookupSpan
+};
 
-lazy_static! {
-    pub static ref LOGGER: Mutex<Logger> = Mutex::new(Logger::new(LogLevel::Warn));
+/// Log level used if neither RUST_LOG nor log_level are set
+const DEFAULT_LOG_LEVEL: &str = "warn";
+/// Target of the output of procedure commands
+/// Output is logged at the info level unless a directive for this target says otherwise
+/// Not a module path so directives for it don't match modules such as influo::output_buffer
+pub const OUTPUT_TARGET: &str = "influo_output";
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum LogFormat {
+    Text, // Human readable lines including the active spans
+    Json, // One flat JSON object per line including the fields of the active spans
+}
+
+/// Installs the global subscriber according to the log_level, log_format and log_output of the configuration
+/// RUST_LOG takes precedence over log_level. Both accept per module directives such as "info,influo::executor=debug"
+pub fn init(config: &Value) -> Result<(), Error> {
+    let mut directives: String = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(d) => d,
+        Err(_) => match &config["log_level"] {
+            Value::Null => DEFAULT_LOG_LEVEL.to_string(),
+            Value::String(s) => s.clone(),
+            _ => return Err(anyhow!("Log level is invalid")),
+        }
+    };
+    // Output of commands is shown regardless of the level of Influo's own messages
+    if !directives.split(',').any(|d| d.trim().split('=').next() == Some(OUTPUT_TARGET)) {
+        directives = format!("{},{}=info", directives, OUTPUT_TARGET);
+    }
+    let filter: EnvFilter = EnvFilter::try_new(&directives).map_err(|e| anyhow!("Log level {} is invalid: {}", directives, e))?;
+
+    let log_format: LogFormat = match &config["log_format"] {
+        Value::Null => LogFormat::Text,
+        raw_log_format => match raw_log_format.as_str().and_then(string_to_log_format) {
+            Some(f) => f,
+            None => return Err(anyhow!("Log format is invalid. Use text or json")),
+        }
+    };
+
+    let writer: BoxMakeWriter = match &config["log_output"] {
+        Value::Null => BoxMakeWriter::new(io::stdout),
+        Value::String(s) if s == "stdout" => BoxMakeWriter::new(io::stdout),
+        Value::String(s) if s == "stderr" => BoxMakeWriter::new(io::stderr),
+        Value::Object(o) if o.get("file").is_some_and(|f| f.is_string()) => {
+            let path: &str = o["file"].as_str().unwrap();
+            let file = OpenOptions::new().create(true).append(true).open(path)
+                .map_err(|e| anyhow!("Unable to open log file {}: {}", path, e))?;
+            BoxMakeWriter::new(Mutex::new(file))
+        },
+        _ => return Err(anyhow!("Log output is invalid. Use stdout, stderr or {{\"file\": \"path\"}}")),
+    };
+
+    let output_layer: Box<dyn Layer<Registry> + Send + Sync> = match log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().with_ansi(false).with_writer(writer).boxed(),
+        LogFormat::Json => JsonLayer { writer: writer }.boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(output_layer)
+        .with(filter)
+        .try_init()
+        .map_err(|e| anyhow!("Unable to set up logging: {}", e))
 }
 
-#[derive(Copy, Clone)]
-pub enum LogLevel {
-    Unknown = 0,
-    Error = 1,
-    Warn = 2,
-    Info = 3,
-    Debug = 4,
+pub fn string_to_log_format(str_format: &str) -> Option<LogFormat> {
+    match str_format {
+        "text" => Some(LogFormat::Text),
+        "json" => Some(LogFormat::Json),
+        _ => None
+    }
 }
 
-pub struct Logger {
-    log_level: LogLevel
+/// Writes every event as a flat JSON object
+/// Fields of the spans an event happened in are included from the outermost span inward
+struct JsonLayer<W> {
+    writer: W,
 }
 
-impl Logger {
-    pub fn new(log_level: LogLevel) -> Logger {
-        Logger {
-            log_level: log_level,
-        }
+/// Fields recorded on a span, stored in the span extensions
+struct SpanFields(Map<String, Value>);
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl<'a> Visit for JsonVisitor<'a> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), Value::from(value));
     }
 
-    pub fn set_log_level(&mut self, log_level: LogLevel) {
-        self.log_level = log_level;
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
     }
 
-    pub fn log(&self, msg: &str, log_level: LogLevel) {
-        let log_level_num = log_level as u8;
-        if log_level_num > self.log_level as u8 {
-            return;
-        }
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
 
-        let level: &str = if log_level_num == 0 {
-            "UNKNOWN"
-        } else if log_level_num == 1 {
-            "ERROR"
-        } else if log_level_num == 2 {
-            "WARN"
-        } else if log_level_num == 3 {
-            "INFO"
-        } else if log_level_num == 4 {
-            "DEBUG"
-        } else {
-            "OTHER"
-        };
-
-        println!("[{}] {}", level, msg);
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), Value::from(value));
     }
 
-    pub fn string_to_log_level(str_level: &str) -> LogLevel {
-        if str_level == "error" {
-            LogLevel::Error
-        } else if str_level == "warn" {
-            LogLevel::Warn
-        } else if str_level == "info" {
-            LogLevel::Info
-        } else if str_level == "debug" {
-            LogLevel::Debug
-        } else {
-            LogLevel::Unknown
-        }
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().to_string(), Value::from(format!("{:?}", value)));
     }
 }
 
-#[macro_export]
-macro_rules! error {
-    ($msg:expr) => {{
-        use $crate::logger::{LOGGER, LogLevel};
-        LOGGER.lock().unwrap().log(&$msg, LogLevel::Error);
-    }}
-}
+impl<S, W> Layer<S> for JsonLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + 'static
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut fields: Map<String, Value> = Map::new();
+            attrs.record(&mut JsonVisitor(&mut fields));
+            span.extensions_mut().insert(SpanFields(fields));
+        }
+    }
 
-#[macro_export]
-macro_rules! warn {
-    ($msg:expr) => {{
-        use $crate::logger::{LOGGER, LogLevel};
-        LOGGER.lock().unwrap().log(&$msg, LogLevel::Warn);
-    }}
-}
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(SpanFields(fields)) = span.extensions_mut().get_mut::<SpanFields>() {
+                values.record(&mut JsonVisitor(fields));
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut record: Map<String, Value> = Map::new();
+        record.insert("timestamp".to_string(), Value::from(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)));
+        record.insert("level".to_string(), Value::from(level_name(metadata.level())));
+        record.insert("target".to_string(), Value::from(metadata.target()));
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    record.extend(fields.clone());
+                }
+            }
+        }
+        event.record(&mut JsonVisitor(&mut record));
 
-#[macro_export]
-macro_rules! info {
-    ($msg:expr) => {{
-        use $crate::logger::{LOGGER, LogLevel};
-        LOGGER.lock().unwrap().log(&$msg, LogLevel::Info);
-    }}
+        let mut line: Vec<u8> = Value::Object(record).to_string().into_bytes();
+        line.push(b'\n');
+        let _ = self.writer.make_writer_for(metadata).write_all(&line);
+    }
 }
 
-#[macro_export]
-macro_rules! debug {
-    ($msg:expr) => {{
-        use $crate::logger::{LOGGER, LogLevel};
-        LOGGER.lock().unwrap().log(&$msg, LogLevel::Debug);
-    }}
-}
\ No newline at end of file
+fn level_name(level: &Level) -> &'static str {
+    match *level {
+        Level::ERROR => "error",
+        Level::WARN => "warn",
+        Level::INFO => "info",
+        Level::DEBUG => "debug",
+        Level::TRACE => "trace",
+    }
+}
@@ -3,128 +3,299 @@ use std::{
     fs,
     thread,
     time::Duration,
-    sync::{Arc, Mutex, RwLock}
+    collections::HashMap,
+    sync::{Arc, Mutex}
 };
 use anyhow::{Error, anyhow};
 use serde_json::Value;
 
 // Project Modules
 #[macro_use]
+extern crate tracing;
+
 mod logger;
 mod model;
 mod system_cmd;
 mod procedure_manager;
+mod pipeline;
+mod limits;
+mod privileges;
+mod executor;
+mod log_files;
+mod output_buffer;
+mod control;
+mod history;
+mod notifier;
+mod commit_status;
 
 use model::{
-    project::Project,
-    channel::message::Command,
-    channel::ThreadProcedureConnection
+    project::{
+        Project,
+        branch::{Branch, CommitDirectives},
+        procedure::Procedure
+    },
+    control::ControlOptions
 };
-use system_cmd::get_remote_git_repository_commits;
-use procedure_manager::run_project_procedure;
-use logger::{LOGGER, Logger};
+use system_cmd::{get_remote_git_repository_commits, get_git_mirror_path, update_git_mirror, get_changed_files, get_commit_metadata};
+use pipeline::{Pipeline, ProcedureConnections};
 
-fn main() -> Result<(), Error> {
-    info!("Influo is running!");
+/// Upper bound for the exponential backoff of a failing remote (2^n times the update interval)
+const MAX_BACKOFF_EXPONENT: u32 = 4;
+/// How often updater threads are checked for panics
+const SUPERVISION_INTERVAL: Duration = Duration::from_secs(1);
 
+/// State of a project shared by its updater thread and running procedures
+/// The state outlives the thread so a respawned thread can still stop the procedures its predecessor started
+#[derive(Clone)]
+struct ProjectState {
+    known_branches: Arc<Mutex<Vec<Branch>>>, // Branches as of the last completed update so a respawned thread only deploys new commits
+    connections: ProcedureConnections,
+    checkout_lock: Arc<Mutex<()>>,
+}
+
+/// An updater thread and the state it shares with running procedures
+struct Updater {
+    project: Project, // As configured. The thread works on its own copy
+    state: ProjectState,
+    handle: thread::JoinHandle<()>,
+}
+
+fn main() -> Result<(), Error> {
     // Load Configuration
+    // Logging is configured by the configuration so the defaults are used to report a missing one
     let raw_config: Result<Value, Error> = read_configuration();
     if raw_config.is_err() {
+        logger::init(&Value::Null)?;
         error!("Configuration not found");
         return Err(raw_config.err().unwrap());
     }
     let config: Value = raw_config.unwrap();
-    if config["log_level"].is_string() {
-        LOGGER.lock().unwrap().set_log_level(Logger::string_to_log_level(&config["log_level"].as_str().unwrap()));
+    logger::init(&config)?;
+
+    // Directory for Influo's own state such as repository mirrors and the run history
+    let data_path: String = match config.get("data_path") {
+        Some(raw_data_path) => match raw_data_path.as_str() {
+            Some(s) => s.to_string(),
+            None => return Err(anyhow!("Data path is invalid")),
+        },
+        None => "./.influo".to_string()
+    };
+
+    // Subcommands inspect the state of a running or previous instance instead of starting one
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(|a| a.as_str()) {
+        Some("history") => return history::run_cli(&args[2..], &data_path),
+        Some(command) => return Err(anyhow!("Unknown command {}. Run without arguments to start Influo or use: history", command)),
+        None => ()
     }
 
+    info!("Influo is running!");
+
     // Process and cache projects
     let raw_projects: &Value = &config["projects"];
     if !raw_projects.is_array() {
         return Err(anyhow!("Projects is invalid"));
     }
     let raw_projects_array: &Vec<Value> = raw_projects.as_array().unwrap();
-    let projects: Arc<Mutex<Vec<Project>>> = Arc::new(Mutex::new(Vec::new()));
+    let mut projects: Vec<Project> = Vec::new();
     for raw_project in raw_projects_array {
-        let mut temp_projects = projects.lock().unwrap();
-        temp_projects.push(Project::new(&raw_project, config.get("default_deploy_path"))?);
+        projects.push(Project::new(&raw_project, &config)?);
     }
 
-    // Retrieve update interval and start the updater thread
+    // Retrieve update interval and start the updater threads
     let raw_update_interval: &Value = &config["update_interval"];
     let update_interval: u32 = if raw_update_interval.is_null() || !raw_update_interval.is_number() {
-        30
+        30 * 1000
     } else {
         let interval: Option<u64> = raw_update_interval.as_u64();
-        if interval.is_none() || interval.unwrap() > std::u32::MAX as u64 {
+        if interval.is_none() || interval.unwrap() > (std::u32::MAX / 1000) as u64 {
             panic!("The integer provided exceeded the u32 max");
         }
         interval.unwrap() as u32 * 1000
     };
-    // let updater_communication: ThreadConnection = ThreadConnection::new();
-    let thread_join_handle: thread::JoinHandle<()> = setup_updater_thread(update_interval, projects);
-    thread_join_handle.join().unwrap();
+    // Maximum time a query to a remote (git ls-remote, mirror fetch) may take
+    let remote_timeout: Duration = match config.get("remote_timeout") {
+        Some(raw_remote_timeout) => match raw_remote_timeout.as_u64() {
+            Some(t) if t > 0 => Duration::from_secs(t),
+            _ => return Err(anyhow!("Remote timeout must be a positive number of seconds")),
+        },
+        None => Duration::from_secs(60)
+    };
+    let history_enabled: bool = match config.get("history") {
+        Some(raw_history) => match raw_history.as_bool() {
+            Some(b) => b,
+            None => return Err(anyhow!("History is invalid")),
+        },
+        None => true
+    };
+    if history_enabled {
+        history::enable(&data_path)?;
+    }
+    notifier::enable(&data_path);
 
-    Ok(())
+    if let Some(control_options) = ControlOptions::new(&config)? {
+        control::start(&control_options)?;
+    }
+
+    // Each project is polled on its own thread so a slow remote doesn't delay the others
+    let mut updaters: Vec<Updater> = Vec::new();
+    for project in projects {
+        let state = ProjectState {
+            known_branches: Arc::new(Mutex::new(Vec::new())),
+            connections: Arc::new(Mutex::new(Vec::new())),
+            checkout_lock: Arc::new(Mutex::new(())),
+        };
+        let handle = setup_updater_thread(update_interval, remote_timeout, data_path.clone(), project.clone(), state.clone(), Duration::ZERO);
+        updaters.push(Updater {
+            project: project,
+            state: state,
+            handle: handle,
+        });
+    }
+
+    // Updater threads only end by panicking. The project would silently stop deploying so the thread is respawned
+    loop {
+        thread::sleep(SUPERVISION_INTERVAL);
+        for updater in updaters.iter_mut() {
+            if !updater.handle.is_finished() {
+                continue;
+            }
+            let interval: u32 = updater.project.update_interval.unwrap_or(update_interval);
+            let handle = setup_updater_thread(update_interval, remote_timeout, data_path.clone(), updater.project.clone(), updater.state.clone(), Duration::from_millis(interval as u64));
+            let finished = std::mem::replace(&mut updater.handle, handle);
+            let reason: String = match finished.join() {
+                Ok(()) => "it exited".to_string(),
+                Err(panic) => match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+                    (Some(message), _) => message.to_string(),
+                    (_, Some(message)) => message.clone(),
+                    _ => "unknown panic".to_string(),
+                },
+            };
+            error!("Updater thread for project with url {} stopped: {}. Restarting it in {} seconds", updater.project.url, reason, interval / 1000);
+        }
+    }
 }
 
-/// Spawns the updater thread for checking updates and controlling procedures
-/// Interval should be in milliseconds
-fn setup_updater_thread(interval: u32, projects: Arc<Mutex<Vec<Project>>>) -> thread::JoinHandle<()> {
-    info!("Spawning updater thread");
+/// Spawns an updater thread for checking updates and controlling procedures of a project
+/// Interval should be in milliseconds and is overridden by the project update interval
+/// The thread waits for the delay before its first update and continues from the known branches
+fn setup_updater_thread(interval: u32, remote_timeout: Duration, data_path: String, mut project: Project, state: ProjectState, delay: Duration) -> thread::JoinHandle<()> {
+    info!("Spawning updater thread for project with url {}", project.url);
+    let ProjectState { known_branches, connections: procedure_thread_connections, checkout_lock } = state;
+    project.update_branches(known_branches.lock().unwrap().clone());
 
-    let mut procedure_thread_connections: Vec<Arc<RwLock<ThreadProcedureConnection>>> = Vec::new();
+    let mut active_pipelines: HashMap<String, Arc<Pipeline>> = HashMap::new();
+    let interval: u32 = project.update_interval.unwrap_or(interval);
 
-    let updater_projects_ref = Arc::clone(&projects);
     thread::spawn(move || {
-        let mut unlocked_projects = updater_projects_ref.lock().unwrap();
+        thread::sleep(delay);
+        let _project_span = info_span!("project", project_url = %project.url).entered();
+        let mut consecutive_failures: u32 = 0;
         loop {
-            debug!("Checking project repositories for updates");
-            for project in &mut *unlocked_projects {
-                let query_result = get_remote_git_repository_commits(&project.url);
-                if query_result.is_err() {
-                    error!(format!("Failed to query commits for project with url {} and error:\n{}", project.url, query_result.err().unwrap()));
+            debug!("Checking project repository {} for updates", project.url);
+            let query_result = get_remote_git_repository_commits(&project.url, remote_timeout);
+            if query_result.is_err() {
+                // Back off exponentially so a failing remote isn't hammered
+                consecutive_failures = (consecutive_failures + 1).min(MAX_BACKOFF_EXPONENT);
+                let backoff: u32 = interval.saturating_mul(1 << consecutive_failures);
+                error!("Failed to query commits for project with url {} and error:\n{}\nRetrying in {} seconds", project.url, query_result.err().unwrap(), backoff / 1000);
+                thread::sleep(Duration::from_millis(backoff as u64));
+                continue;
+            }
+            consecutive_failures = 0;
+
+            let mut branches = query_result.unwrap();
+
+            // New commits are inspected (metadata and changed files) using a local mirror
+            let has_updates: bool = branches.iter().any(|branch| !project.branches.iter().any(|b| b.name == branch.name && b.latest_commit_hash == branch.latest_commit_hash));
+            let mirror_path: String = get_git_mirror_path(&data_path, &project.url);
+            let mut mirror_available = false;
+            if has_updates {
+                match update_git_mirror(&project.url, &mirror_path, remote_timeout) {
+                    Ok(()) => mirror_available = true,
+                    Err(e) => warn!("Failed to update mirror for project with url {}. Commit directives and path filters will be ignored:\n{}", project.url, e),
+                }
+            }
+
+            for branch in branches.iter_mut() {
+                let short_hash: String = branch.latest_commit_hash.chars().take(5).collect();
+                let _branch_span = info_span!("branch", branch = %branch.name, commit = %branch.latest_commit_hash).entered();
+                debug!("Current branch is {}. Current short commit hash is {}", branch.name, short_hash);
+                if let Some(previous) = project.branches.iter().find(|&b| b.name == branch.name) {
+                    if previous.latest_commit_hash == branch.latest_commit_hash {
+                        branch.metadata = previous.metadata.clone();
+                        continue;
+                    }
+                }
+
+                if mirror_available {
+                    match get_commit_metadata(&mirror_path, &branch.latest_commit_hash) {
+                        Ok(metadata) => {
+                            debug!("Commit {} was authored by {} at {}", short_hash, metadata.author, metadata.timestamp.to_rfc3339());
+                            branch.metadata = Some(metadata);
+                        },
+                        Err(e) => warn!("Failed to retrieve metadata for commit {}:\n{}", short_hash, e),
+                    }
+                }
+                let directives: CommitDirectives = match &branch.metadata {
+                    Some(metadata) => metadata.directives(),
+                    None => CommitDirectives::default()
+                };
+                if directives.skip_all {
+                    info!("Skipping commit {} in the {} branch as requested by the commit message", short_hash, branch.name);
                     continue;
                 }
 
-                let branches = query_result.unwrap();
-                for branch in &branches {
-                    let short_hash: String = branch.latest_commit_hash.chars().take(5).collect();
-                    debug!(format!("Current branch is {}. Current short commit hash is {}", branch.name, short_hash));
-                    let branch_search = project.branches.iter().find(|&b| b.name == branch.name);
-                    if branch_search.is_some() && branch_search.unwrap().latest_commit_hash == branch.latest_commit_hash {
+                info!("Updating to commit {} in the {} branch...", short_hash, branch.name);
+                let mut triggered_procedures: Vec<Procedure> = Vec::new();
+                for procedure in &project.procedures {
+                    let branch_in_procedure = procedure.branches.iter().find(|&b| *b == branch.name);
+                    if branch_in_procedure.is_none() {
                         continue;
                     }
 
-                    info!(format!("Updating to commit {} in the {} branch...", short_hash, branch.name));
-                    for procedure in &project.procedures {
-                        let branch_in_procedure = procedure.branches.iter().find(|&b| *b == branch.name);
-                        if branch_in_procedure.is_none() {
-                            continue;
-                        }
+                    if directives.skipped_procedures.contains(&procedure.name) {
+                        info!(procedure = %procedure.name, "[{}] Skipping procedure as requested by the commit message", procedure.name);
+                        continue;
+                    }
 
-                        // Kill previous procedure process
-                        for unlocked_procedure_thread_connection in &procedure_thread_connections {
-                            let procedure_thread_connection = &unlocked_procedure_thread_connection.read().unwrap();
-                            if procedure_thread_connection.remote_url == project.url && procedure_thread_connection.branch == branch.name && procedure_thread_connection.procedure_name == procedure.name {
-                                info!(format!("[{}] Found previous running version. Attempting to send kill message", procedure.name));
-                                let sen = &procedure_thread_connection.owner_channel.sender.read().unwrap();
-                                sen.send(Command::KillProcedure).expect("Failed to send kill command!");
-                                // TODO: Wait for response/timeout
-                            }
+                    // Skip the procedure if none of the files it cares about changed since its last deployment
+                    // The history survives restarts. Procedures started since Influo started are used if it is disabled
+                    let previous_commit: Option<String> = if procedure.has_path_filters() && !directives.force && mirror_available {
+                        history::last_succeeded_commit(&project.url, &branch.name, &procedure.name)
+                            .or_else(|| procedure_thread_connections.lock().unwrap().iter()
+                                .rev()
+                                .map(|c| c.read().unwrap())
+                                .find(|c| c.remote_url == project.url && c.branch == branch.name && c.procedure_name == procedure.name)
+                                .map(|c| c.commit.clone()))
+                    } else {
+                        None
+                    };
+                    if let Some(previous) = &previous_commit {
+                        match get_changed_files(&mirror_path, previous, &branch.latest_commit_hash) {
+                            Ok(changed_files) => {
+                                if !procedure.is_triggered_by(&changed_files) {
+                                    info!(procedure = %procedure.name, "[{}] No relevant files changed in commit {}. Skipping procedure", procedure.name, short_hash);
+                                    continue;
+                                }
+                            },
+                            Err(e) => warn!(procedure = %procedure.name, "[{}] Failed to diff commits. Running procedure regardless:\n{}", procedure.name, e),
                         }
+                    }
 
-                        // Insert new connection
-                        procedure_thread_connections.push(Arc::new(RwLock::new(ThreadProcedureConnection::new(project.url.clone(), branch.name.clone(), procedure.name.clone()))));
-                        let procedure_connection = procedure_thread_connections.last_mut().unwrap();
+                    triggered_procedures.push(procedure.clone());
+                }
 
-                        // Run procedure
-                        run_project_procedure(&project, &branch, &procedure, Arc::clone(&procedure_connection)).expect("Procedure failed due to a git error!");
-                    }
+                // Procedures of an outdated commit which have not started yet should no longer start
+                if let Some(previous_pipeline) = active_pipelines.remove(&branch.name) {
+                    previous_pipeline.cancel();
                 }
-                project.update_branches(branches);
+                let pipeline = Pipeline::start(&project, branch, triggered_procedures, Arc::clone(&procedure_thread_connections), Arc::clone(&checkout_lock));
+                active_pipelines.insert(branch.name.clone(), pipeline);
             }
-            debug!(format!("Updater thread sleeping for {} seconds", interval / 1000));
+            project.update_branches(branches);
+            *known_branches.lock().unwrap() = project.branches.clone();
+            debug!("Updater thread for {} sleeping for {} seconds", project.url, interval / 1000);
             thread::sleep(Duration::from_millis(interval as u64));
         }
     })
@@ -6,6 +6,7 @@ pub enum Command {
 
 #[derive(Clone, Debug)]
 pub enum Response {
+    ProcedureCompleted(bool), // Whether every command completed successfully
     // KilledProcedure(i32), // Close Code
     // Logs(Vec<String>), // Line separated logs
 }
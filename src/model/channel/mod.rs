@@ -39,18 +39,20 @@ pub struct ThreadProcedureConnection {
     pub remote_url: String,
     pub branch: String,
     pub procedure_name: String,
+    pub commit: String, // Commit the procedure was deployed with
     pub owner_channel: Channel<Command>, // Channel for the owner thread to send
     pub child_channel: Channel<Response>, // Channel for the child thread to send (spawned by owner)
 }
 
 impl ThreadProcedureConnection {
-    pub fn new(remote_url: String, branch: String, procedure_name: String) -> ThreadProcedureConnection {
+    pub fn new(remote_url: String, branch: String, procedure_name: String, commit: String) -> ThreadProcedureConnection {
         let (owner_sender, owner_receiver) = unbounded_channel();
         let (child_sender, child_receiver) = unbounded_channel();
         ThreadProcedureConnection {
             remote_url: remote_url,
             branch: branch,
             procedure_name: procedure_name,
+            commit: commit,
             owner_channel: Channel::<Command> {
                 receiver: RwLock::new(owner_receiver),
                 sender: RwLock::new(owner_sender),
@@ -0,0 +1,69 @@
+use std::net::SocketAddr;
+use anyhow::{Error, anyhow};
+use serde_json::Value;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:7420";
+const DEFAULT_BUFFER_LINES: usize = 1000;
+
+/// HTTP interface for inspecting procedures while Influo runs
+#[derive(Debug, Clone)]
+pub struct ControlOptions {
+    pub address: SocketAddr,
+    pub buffer_lines: usize, // Lines of output kept per procedure
+    pub token: Option<String>, // Clients have to send it as a bearer token if set
+}
+
+impl ControlOptions {
+    /// The control interface is disabled unless configured
+    pub fn new(raw_config: &Value) -> Result<Option<ControlOptions>, Error> {
+        let raw_control = match raw_config.get("control") {
+            Some(v) => match v.as_object() {
+                Some(o) => o,
+                None => return Err(anyhow!("Control is invalid")),
+            },
+            None => return Ok(None),
+        };
+
+        let address: SocketAddr = match raw_control.get("address") {
+            Some(raw_address) => match raw_address.as_str().and_then(|a| a.parse().ok()) {
+                Some(a) => a,
+                None => return Err(anyhow!("Control address is invalid. Use an address such as {}", DEFAULT_ADDRESS)),
+            },
+            None => DEFAULT_ADDRESS.parse().unwrap()
+        };
+
+        let buffer_lines: usize = match raw_control.get("buffer_lines") {
+            Some(raw_buffer_lines) => match raw_buffer_lines.as_u64() {
+                Some(lines) if lines > 0 => lines as usize,
+                _ => return Err(anyhow!("Control buffer_lines must be a positive number")),
+            },
+            None => DEFAULT_BUFFER_LINES
+        };
+
+        let optional_string = |key: &str| -> Result<Option<&str>, Error> {
+            match raw_control.get(key) {
+                Some(v) => match v.as_str() {
+                    Some(s) if !s.is_empty() => Ok(Some(s)),
+                    _ => Err(anyhow!("Control {} is invalid", key)),
+                },
+                None => Ok(None)
+            }
+        };
+        // Read once at startup. Unlike commit statuses the interface would otherwise be open, so a missing variable is an error
+        let token: Option<String> = match (optional_string("token")?, optional_string("token_env")?) {
+            (Some(t), None) => Some(t.to_string()),
+            (None, Some(e)) => match std::env::var(e) {
+                Ok(t) if !t.is_empty() => Some(t),
+                _ => return Err(anyhow!("Environment variable {} holding the control token is not set", e)),
+            },
+            (None, None) => None,
+            (Some(_), Some(_)) => return Err(anyhow!("Control needs either token or token_env, not both")),
+        };
+
+        Ok(Some(ControlOptions {
+            address: address,
+            buffer_lines: buffer_lines,
+            token: token,
+        }))
+    }
+}
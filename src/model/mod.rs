@@ -1,2 +1,3 @@
 pub mod project;
-pub mod channel;
\ No newline at end of file
+pub mod channel;
+pub mod control;
\ No newline at end of file
@@ -1,5 +1,47 @@
-#[derive(Debug)]
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref DIRECTIVE_PATTERN: Regex = Regex::new(r"(?i)\[\s*(?:skip\s+influo|influo\s+skip(?:\s*:\s*([^\]]+?))?|influo\s+(force))\s*\]").unwrap();
+}
+
+#[derive(Debug, Clone)]
 pub struct Branch {
     pub name: String,
     pub latest_commit_hash: String,
-}
\ No newline at end of file
+    pub metadata: Option<CommitMetadata>, // Only retrieved for commits that trigger an update
+}
+
+#[derive(Debug, Clone)]
+pub struct CommitMetadata {
+    pub author: String,
+    pub message: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Deployment directives found in a commit message
+#[derive(Debug, Default)]
+pub struct CommitDirectives {
+    pub skip_all: bool, // [skip influo] or [influo skip]
+    pub skipped_procedures: Vec<String>, // [influo skip:procedure_name] or [influo skip: first, second]
+    pub force: bool, // [influo force] ignores path filters
+}
+
+impl CommitMetadata {
+    pub fn directives(&self) -> CommitDirectives {
+        let mut directives = CommitDirectives::default();
+        for capture in DIRECTIVE_PATTERN.captures_iter(&self.message) {
+            if capture.get(2).is_some() {
+                directives.force = true;
+            } else if let Some(procedure_names) = capture.get(1) {
+                let names = procedure_names.as_str().split(',').map(|n| n.trim()).filter(|n| !n.is_empty());
+                directives.skipped_procedures.extend(names.map(|n| n.to_string()));
+            } else {
+                directives.skip_all = true;
+            }
+        }
+
+        directives
+    }
+}
@@ -0,0 +1,86 @@
+use std::time::Duration;
+use anyhow::{Error, anyhow};
+use serde_json::Value;
+
+#[derive(Debug, Clone)]
+pub struct CheckoutOptions {
+    pub submodules: SubmoduleMode,
+    pub lfs: bool,
+    pub depth: Option<u32>, // Shallow clone/fetch depth
+    pub sparse_paths: Vec<String>, // Directories checked out using a cone mode sparse checkout
+    pub timeout: Duration, // Maximum time each git command of a checkout (clone, fetch, submodules, LFS) may take
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubmoduleMode {
+    Disabled, // Submodules are left uninitialized
+    TopLevel, // Only the submodules of the repository are initialized
+    Recursive, // Submodules of submodules are initialized as well
+}
+
+impl CheckoutOptions {
+    pub fn new(raw_project: &Value) -> Result<CheckoutOptions, Error> {
+        let submodules: SubmoduleMode = match raw_project.get("submodules") {
+            Some(raw_submodules) => match raw_submodules.as_bool() {
+                Some(true) => SubmoduleMode::TopLevel,
+                Some(false) => SubmoduleMode::Disabled,
+                None => match raw_submodules.as_str() {
+                    Some("recursive") => SubmoduleMode::Recursive,
+                    _ => return Err(anyhow!("Submodules must be a boolean or \"recursive\" in project")),
+                }
+            },
+            None => SubmoduleMode::Disabled
+        };
+
+        let lfs: bool = match raw_project.get("lfs") {
+            Some(raw_lfs) => match raw_lfs.as_bool() {
+                Some(b) => b,
+                None => return Err(anyhow!("LFS is invalid in project")),
+            },
+            None => false
+        };
+
+        let depth: Option<u32> = match raw_project.get("depth") {
+            Some(raw_depth) => match raw_depth.as_u64() {
+                Some(d) => {
+                    if d == 0 || d > std::u32::MAX as u64 {
+                        return Err(anyhow!("Depth must be a positive u32 in project"));
+                    }
+                    Some(d as u32)
+                },
+                None => return Err(anyhow!("Depth is invalid in project")),
+            },
+            None => None
+        };
+
+        let mut sparse_paths: Vec<String> = Vec::new();
+        if let Some(raw_sparse_paths) = raw_project.get("sparse_paths") {
+            let raw_sparse_paths_array: &Vec<Value> = match raw_sparse_paths.as_array() {
+                Some(v) => v,
+                None => return Err(anyhow!("Sparse paths is invalid in project")),
+            };
+            for raw_sparse_path in raw_sparse_paths_array {
+                match raw_sparse_path.as_str() {
+                    Some(s) if !s.is_empty() => sparse_paths.push(s.to_string()),
+                    _ => return Err(anyhow!("Project sparse path is invalid")),
+                }
+            }
+        }
+
+        let timeout: Duration = match raw_project.get("checkout_timeout") {
+            Some(raw_timeout) => match raw_timeout.as_u64() {
+                Some(t) if t > 0 => Duration::from_secs(t),
+                _ => return Err(anyhow!("Checkout timeout must be a positive number of seconds in project")),
+            },
+            None => Duration::from_secs(10 * 60)
+        };
+
+        Ok(CheckoutOptions {
+            submodules: submodules,
+            lfs: lfs,
+            depth: depth,
+            sparse_paths: sparse_paths,
+            timeout: timeout,
+        })
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    time::Duration,
+    path::{Path, Component}
+};
+use anyhow::{Error, anyhow};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::Value;
+
+use super::procedure::parse_timeout;
+
+lazy_static! {
+    // Names a shell accepts unquoted, as remote hosts export them in a script
+    static ref ENV_NAME_PATTERN: Regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcedureCommand {
+    pub command: String,
+    pub kind: CommandKind,
+    pub retries: u32, // Additional attempts for a failed run command
+    pub timeout: Option<Duration>, // The command is stopped and considered failed after the timeout
+    pub cwd: Option<String>, // Directory relative to the checkout the command runs in instead of the checkout itself
+    pub env: Vec<(String, String)>, // Added to the procedure environment and overriding it
+    pub stdin: Option<CommandInput>, // The command reads from an empty input if unset
+    pub continue_on_error: bool, // The procedure continues with the next command once a failed run command ran out of retries
+}
+
+#[derive(Debug, Clone)]
+pub enum CommandInput {
+    Literal(String),
+    File(String), // Relative to the working directory of the command and has to stay inside the checkout
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandKind {
+    Run, // One-shot step which must exit successfully and is never restarted
+    Service, // Expected to run forever and restarted according to the auto restart policy
+}
+
+impl ProcedureCommand {
+    /// Plain string commands are run commands except for the last command of a procedure which is the service
+    pub fn new(raw_command: &Value, is_last: bool) -> Result<ProcedureCommand, Error> {
+        if let Some(s) = raw_command.as_str() {
+            let kind: CommandKind = if is_last { CommandKind::Service } else { CommandKind::Run };
+            return Ok(ProcedureCommand {
+                command: s.to_string(),
+                timeout: None,
+                kind: kind,
+                retries: 0,
+                cwd: None,
+                env: Vec::new(),
+                stdin: None,
+                continue_on_error: false,
+            });
+        }
+
+        let raw_command_object = match raw_command.as_object() {
+            Some(o) => o,
+            None => return Err(anyhow!("Procedure command is invalid")),
+        };
+        let (command, kind): (&str, CommandKind) = match (raw_command_object.get("run"), raw_command_object.get("service")) {
+            (Some(raw_run), None) => match raw_run.as_str() {
+                Some(s) => (s, CommandKind::Run),
+                None => return Err(anyhow!("Procedure run command is invalid")),
+            },
+            (None, Some(raw_service)) => match raw_service.as_str() {
+                Some(s) => (s, CommandKind::Service),
+                None => return Err(anyhow!("Procedure service command is invalid")),
+            },
+            (Some(_), Some(_)) => return Err(anyhow!("Procedure command can't be both a run and a service command")),
+            (None, None) => return Err(anyhow!("Procedure command object requires either run or service")),
+        };
+
+        let retries: u32 = match raw_command_object.get("retries") {
+            Some(raw_retries) => {
+                if kind == CommandKind::Service {
+                    return Err(anyhow!("Retries is only valid for run commands. Use auto_restart for services"));
+                }
+                match raw_retries.as_u64() {
+                    Some(r) if r <= std::u32::MAX as u64 => r as u32,
+                    _ => return Err(anyhow!("Procedure command retries is invalid")),
+                }
+            },
+            None => 0
+        };
+
+        let timeout: Option<Duration> = parse_timeout(raw_command_object.get("timeout"))?;
+
+        let cwd: Option<String> = match raw_command_object.get("cwd") {
+            Some(raw_cwd) => match raw_cwd.as_str() {
+                // The command must stay inside the checkout
+                Some(s) if !s.is_empty() && Path::new(s).components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) => Some(s.to_string()),
+                _ => return Err(anyhow!("Procedure command cwd is invalid. It must be a path inside the repository")),
+            },
+            None => None
+        };
+
+        let mut env: Vec<(String, String)> = Vec::new();
+        if let Some(raw_env) = raw_command_object.get("env") {
+            let raw_env_object = match raw_env.as_object() {
+                Some(o) => o,
+                None => return Err(anyhow!("Procedure command env is invalid")),
+            };
+            for (key, raw_value) in raw_env_object {
+                if !ENV_NAME_PATTERN.is_match(key) {
+                    return Err(anyhow!("Procedure command env variable name {} is invalid", key));
+                }
+                match raw_value.as_str() {
+                    Some(v) => env.push((key.clone(), v.to_string())),
+                    None => return Err(anyhow!("Procedure command env variable {} is invalid", key)),
+                }
+            }
+        }
+
+        let stdin: Option<CommandInput> = match raw_command_object.get("stdin") {
+            Some(Value::String(s)) => Some(CommandInput::Literal(s.clone())),
+            Some(Value::Object(raw_stdin_object)) => match raw_stdin_object.get("file").and_then(|f| f.as_str()) {
+                Some(f) if !f.is_empty() && raw_stdin_object.len() == 1 => Some(CommandInput::File(f.to_string())),
+                _ => return Err(anyhow!("Procedure command stdin is invalid. Use a string or an object with a file")),
+            },
+            Some(_) => return Err(anyhow!("Procedure command stdin is invalid. Use a string or an object with a file")),
+            None => None
+        };
+
+        let continue_on_error: bool = match raw_command_object.get("continue_on_error") {
+            Some(raw_continue_on_error) => {
+                if kind == CommandKind::Service {
+                    return Err(anyhow!("Continue_on_error is only valid for run commands. Use auto_restart for services"));
+                }
+                match raw_continue_on_error.as_bool() {
+                    Some(b) => b,
+                    None => return Err(anyhow!("Procedure command continue_on_error is invalid")),
+                }
+            },
+            None => false
+        };
+
+        Ok(ProcedureCommand {
+            command: command.to_string(),
+            kind: kind,
+            retries: retries,
+            timeout: timeout,
+            cwd: cwd,
+            env: env,
+            stdin: stdin,
+            continue_on_error: continue_on_error,
+        })
+    }
+}
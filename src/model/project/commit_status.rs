@@ -0,0 +1,127 @@
+use anyhow::{Error, anyhow};
+use regex::Regex;
+use serde_json::Value;
+
+use super::log_pattern::LogPattern;
+
+/// Context statuses are reported under unless configured. One status per procedure
+const DEFAULT_CONTEXT: &str = "influo/{name}";
+
+/// Git forges whose commit status API is supported
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Forge {
+    GitHub,
+    GitLab,
+    Gitea, // Also Forgejo
+}
+
+#[derive(Debug, Clone)]
+pub enum TokenSource {
+    Value(String),
+    Env(String), // Environment variable holding the token so it isn't stored in the configuration
+}
+
+impl TokenSource {
+    pub fn resolve(&self) -> Result<String, Error> {
+        match self {
+            TokenSource::Value(token) => Ok(token.clone()),
+            TokenSource::Env(variable) => std::env::var(variable).map_err(|_| anyhow!("Environment variable {} holding the commit status token is not set", variable)),
+        }
+    }
+}
+
+/// Reports the state of procedure runs as statuses of the commit they ran against
+#[derive(Debug, Clone)]
+pub struct CommitStatusOptions {
+    pub forge: Forge,
+    pub api_url: String, // Without a trailing slash
+    pub repository: String, // Path of the repository on the forge such as owner/repo
+    pub token: TokenSource,
+    pub context: LogPattern, // Name of the status. Statuses with the same context replace each other
+    pub target_url: Option<LogPattern>, // Link shown next to the status
+}
+
+impl CommitStatusOptions {
+    /// Commit statuses are disabled unless configured for the project
+    pub fn new(raw_project: &Value, project_url: &str) -> Result<Option<CommitStatusOptions>, Error> {
+        let raw_commit_status = match raw_project.get("commit_status") {
+            Some(v) => match v.as_object() {
+                Some(o) => o,
+                None => return Err(anyhow!("Commit status is invalid in project")),
+            },
+            None => return Ok(None),
+        };
+        let optional_string = |key: &str| -> Result<Option<&str>, Error> {
+            match raw_commit_status.get(key) {
+                Some(v) => match v.as_str() {
+                    Some(s) if !s.is_empty() => Ok(Some(s)),
+                    _ => Err(anyhow!("Commit status {} is invalid", key)),
+                },
+                None => Ok(None)
+            }
+        };
+
+        let forge: Forge = match optional_string("provider")? {
+            Some("github") => Forge::GitHub,
+            Some("gitlab") => Forge::GitLab,
+            Some("gitea") | Some("forgejo") => Forge::Gitea,
+            Some(p) => return Err(anyhow!("Commit status provider {} is invalid. Use github, gitlab or gitea", p)),
+            None => return Err(anyhow!("Commit status provider not found")),
+        };
+
+        let token: TokenSource = match (optional_string("token")?, optional_string("token_env")?) {
+            (Some(t), None) => TokenSource::Value(t.to_string()),
+            (None, Some(e)) => TokenSource::Env(e.to_string()),
+            _ => return Err(anyhow!("Commit status needs either token or token_env")),
+        };
+        if let Err(e) = token.resolve() {
+            warn!("{}. Commit statuses of {} won't be reported until it is set", e, project_url);
+        }
+
+        let (host, path) = split_remote_url(project_url);
+        let repository: String = match (optional_string("repository")?, path) {
+            (Some(r), _) => r.trim_matches('/').to_string(),
+            (None, Some(p)) => p,
+            (None, None) => return Err(anyhow!("Commit status repository can't be derived from the project URL. Set repository")),
+        };
+        let api_url: String = match (optional_string("api_url")?, host) {
+            (Some(u), _) => u.trim_end_matches('/').to_string(),
+            (None, Some(h)) => default_api_url(forge, &h),
+            (None, None) => return Err(anyhow!("Commit status API URL can't be derived from the project URL. Set api_url")),
+        };
+
+        let context: LogPattern = LogPattern::message_template(optional_string("context")?.unwrap_or(DEFAULT_CONTEXT))?;
+        let target_url: Option<LogPattern> = match optional_string("target_url")? {
+            Some(t) => Some(LogPattern::message_template(t)?),
+            None => None
+        };
+
+        Ok(Some(CommitStatusOptions {
+            forge: forge,
+            api_url: api_url,
+            repository: repository,
+            token: token,
+            context: context,
+            target_url: target_url,
+        }))
+    }
+}
+
+/// Splits a remote URL such as https://host/owner/repo.git or git@host:owner/repo.git into its host and repository path
+fn split_remote_url(remote_url: &str) -> (Option<String>, Option<String>) {
+    let regex_pattern = Regex::new(r"^(?:[a-z+]+://)?(?:[^@/]+@)?([^/:]+)(?::\d+)?[/:]/?(.+?)(?:\.git)?/?$").unwrap();
+    match regex_pattern.captures(remote_url) {
+        Some(captures) => (Some(captures[1].to_string()), Some(captures[2].to_string())),
+        None => (None, None)
+    }
+}
+
+/// API of the forge hosting the repository. Self-hosted instances are assumed to serve it over HTTPS on the same host
+fn default_api_url(forge: Forge, host: &str) -> String {
+    match forge {
+        Forge::GitHub if host == "github.com" => "https://api.github.com".to_string(),
+        Forge::GitHub => format!("https://{}/api/v3", host), // GitHub Enterprise Server
+        Forge::GitLab => format!("https://{}/api/v4", host),
+        Forge::Gitea => format!("https://{}/api/v1", host),
+    }
+}
@@ -0,0 +1,68 @@
+use anyhow::{Error, anyhow};
+use serde_json::Value;
+
+/// Commands of the procedure run inside a container using a locally installed runtime CLI
+#[derive(Debug, Clone)]
+pub struct ContainerOptions {
+    pub image: String,
+    pub runtime: String, // docker, podman or any CLI compatible with docker run
+    pub options: Vec<String>, // Additional arguments for the run command
+    pub pass_env: Vec<String>, // Variables of Influo's environment passed to the container
+}
+
+impl ContainerOptions {
+    pub fn new(raw_procedure: &Value) -> Result<Option<ContainerOptions>, Error> {
+        let raw_container = match raw_procedure.get("container") {
+            Some(v) => match v.as_object() {
+                Some(o) => o,
+                None => return Err(anyhow!("Container is invalid in procedure")),
+            },
+            None => return Ok(None),
+        };
+
+        let image: &str = match raw_container.get("image") {
+            Some(raw_image) => match raw_image.as_str() {
+                Some(s) if !s.is_empty() => s,
+                _ => return Err(anyhow!("Container image is invalid in procedure")),
+            },
+            None => return Err(anyhow!("Container image not found in procedure")),
+        };
+
+        let runtime: &str = match raw_container.get("runtime") {
+            Some(raw_runtime) => match raw_runtime.as_str() {
+                Some(s) if !s.is_empty() => s,
+                _ => return Err(anyhow!("Container runtime is invalid in procedure")),
+            },
+            None => "docker"
+        };
+
+        let options: Vec<String> = parse_string_array(raw_container.get("options"), "Container options")?;
+        let pass_env: Vec<String> = parse_string_array(raw_container.get("pass_env"), "Container pass_env")?;
+
+        Ok(Some(ContainerOptions {
+            image: image.to_string(),
+            runtime: runtime.to_string(),
+            options: options,
+            pass_env: pass_env,
+        }))
+    }
+}
+
+pub fn parse_string_array(raw_array: Option<&Value>, description: &str) -> Result<Vec<String>, Error> {
+    let mut values: Vec<String> = Vec::new();
+    let raw_values: &Vec<Value> = match raw_array {
+        Some(v) => match v.as_array() {
+            Some(a) => a,
+            None => return Err(anyhow!("{} is invalid in procedure", description)),
+        },
+        None => return Ok(values),
+    };
+    for raw_value in raw_values {
+        match raw_value.as_str() {
+            Some(s) => values.push(s.to_string()),
+            None => return Err(anyhow!("{} contains an invalid value", description)),
+        }
+    }
+
+    Ok(values)
+}
@@ -0,0 +1,106 @@
+use anyhow::{Error, anyhow};
+use serde_json::Value;
+
+/// Resource limits applied to every process spawned by a procedure
+#[derive(Debug, Clone, Default)]
+pub struct ResourceLimits {
+    pub memory: Option<u64>, // Bytes (cgroup memory.max or RLIMIT_AS without cgroups)
+    pub cpu_weight: Option<u64>, // cgroup cpu.weight (1-10000)
+    pub nice: Option<i32>, // Scheduling priority (-20 to 19)
+    pub open_files: Option<u64>, // RLIMIT_NOFILE
+    pub processes: Option<u64>, // cgroup pids.max or RLIMIT_NPROC without cgroups, which counts every process of the user and not only the procedure's
+    pub cgroup_root: Option<String>, // Delegated cgroup v2 directory which procedure sub-trees are created in
+}
+
+impl ResourceLimits {
+    pub fn new(raw_procedure: &Value, raw_default_cgroup_root: Option<&Value>) -> Result<ResourceLimits, Error> {
+        let raw_limits = match raw_procedure.get("limits") {
+            Some(v) => match v.as_object() {
+                Some(o) => o,
+                None => return Err(anyhow!("Limits is invalid in procedure")),
+            },
+            None => return Ok(ResourceLimits::default()),
+        };
+
+        let memory: Option<u64> = match raw_limits.get("memory") {
+            Some(raw_memory) => Some(parse_byte_size(raw_memory)?),
+            None => None
+        };
+
+        let cpu_weight: Option<u64> = match raw_limits.get("cpu_weight") {
+            Some(raw_cpu_weight) => match raw_cpu_weight.as_u64() {
+                Some(w) if (1..=10000).contains(&w) => Some(w),
+                _ => return Err(anyhow!("CPU weight must be between 1 and 10000 in procedure limits")),
+            },
+            None => None
+        };
+
+        let nice: Option<i32> = match raw_limits.get("nice") {
+            Some(raw_nice) => match raw_nice.as_i64() {
+                Some(n) if (-20..=19).contains(&n) => Some(n as i32),
+                _ => return Err(anyhow!("Nice must be between -20 and 19 in procedure limits")),
+            },
+            None => None
+        };
+
+        let open_files: Option<u64> = match raw_limits.get("open_files") {
+            Some(raw_open_files) => match raw_open_files.as_u64() {
+                Some(n) if n > 0 => Some(n),
+                _ => return Err(anyhow!("Open files is invalid in procedure limits")),
+            },
+            None => None
+        };
+
+        let processes: Option<u64> = match raw_limits.get("processes") {
+            Some(raw_processes) => match raw_processes.as_u64() {
+                Some(n) if n > 0 => Some(n),
+                _ => return Err(anyhow!("Processes is invalid in procedure limits")),
+            },
+            None => None
+        };
+
+        let cgroup_root: Option<String> = match raw_limits.get("cgroup_root").or(raw_default_cgroup_root) {
+            Some(raw_cgroup_root) => match raw_cgroup_root.as_str() {
+                Some(s) => Some(s.to_string()),
+                None => return Err(anyhow!("Cgroup root is invalid")),
+            },
+            None => None
+        };
+
+        Ok(ResourceLimits {
+            memory: memory,
+            cpu_weight: cpu_weight,
+            nice: nice,
+            open_files: open_files,
+            processes: processes,
+            cgroup_root: cgroup_root,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_none() && self.cpu_weight.is_none() && self.nice.is_none() && self.open_files.is_none() && self.processes.is_none()
+    }
+}
+
+/// Parses a size in bytes from either an integer or a string with a K, M, G or T suffix (powers of 1024)
+pub fn parse_byte_size(raw_size: &Value) -> Result<u64, Error> {
+    if let Some(bytes) = raw_size.as_u64() {
+        return Ok(bytes);
+    }
+
+    let size: &str = match raw_size.as_str() {
+        Some(s) => s.trim(),
+        None => return Err(anyhow!("Size is not an integer or string")),
+    };
+    let (number, multiplier): (&str, u64) = match size.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&size[..size.len() - 1], 1 << 10),
+        Some('M') => (&size[..size.len() - 1], 1 << 20),
+        Some('G') => (&size[..size.len() - 1], 1 << 30),
+        Some('T') => (&size[..size.len() - 1], 1 << 40),
+        _ => (size, 1),
+    };
+    match number.trim().parse::<u64>() {
+        Ok(n) => n.checked_mul(multiplier).ok_or_else(|| anyhow!("Size {} is too large", size)),
+        Err(_) => Err(anyhow!("Size {} is invalid", size)),
+    }
+}
@@ -0,0 +1,66 @@
+use std::time::Duration;
+use anyhow::{Error, anyhow};
+use serde_json::Value;
+
+use super::limits::parse_byte_size;
+
+/// Output of every procedure run is written to its own file inside the log directory
+#[derive(Debug, Clone)]
+pub struct LogFileOptions {
+    pub directory: String,
+    pub max_size: Option<u64>, // Files are rotated once they would grow beyond this size (bytes)
+    pub max_age: Option<Duration>, // Older files are deleted when a procedure starts
+    pub compress: bool, // Rotated files and files of previous runs are compressed with gzip
+}
+
+impl LogFileOptions {
+    /// Log files are disabled unless a log directory is configured
+    pub fn new(raw_config: &Value) -> Result<Option<LogFileOptions>, Error> {
+        let raw_log_files = match raw_config.get("log_files") {
+            Some(v) => match v.as_object() {
+                Some(o) => o,
+                None => return Err(anyhow!("Log files is invalid")),
+            },
+            None => return Ok(None),
+        };
+
+        let directory: &str = match raw_log_files.get("directory") {
+            Some(raw_directory) => match raw_directory.as_str() {
+                Some(s) if !s.is_empty() => s,
+                _ => return Err(anyhow!("Log files directory is invalid")),
+            },
+            None => return Err(anyhow!("Log files directory not found")),
+        };
+
+        let max_size: Option<u64> = match raw_log_files.get("max_size") {
+            Some(raw_max_size) => match parse_byte_size(raw_max_size) {
+                Ok(size) if size > 0 => Some(size),
+                _ => return Err(anyhow!("Log files max_size is invalid")),
+            },
+            None => None
+        };
+
+        let max_age: Option<Duration> = match raw_log_files.get("max_age") {
+            Some(raw_max_age) => match raw_max_age.as_u64().and_then(|days| days.checked_mul(24 * 60 * 60)) {
+                Some(seconds) if seconds > 0 => Some(Duration::from_secs(seconds)),
+                _ => return Err(anyhow!("Log files max_age must be a positive number of days")),
+            },
+            None => None
+        };
+
+        let compress: bool = match raw_log_files.get("compress") {
+            Some(raw_compress) => match raw_compress.as_bool() {
+                Some(b) => b,
+                None => return Err(anyhow!("Log files compress is invalid")),
+            },
+            None => true
+        };
+
+        Ok(Some(LogFileOptions {
+            directory: directory.to_string(),
+            max_size: max_size,
+            max_age: max_age,
+            compress: compress,
+        }))
+    }
+}
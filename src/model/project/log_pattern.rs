@@ -0,0 +1,213 @@
+use anyhow::{Error, anyhow};
+use serde_json::Value;
+use chrono::{Utc, Local, FixedOffset, format::{StrftimeItems, Item}};
+
+/// Format of {time} without an explicit format
+const DEFAULT_TIME_FORMAT: &str = "%H:%M:%S";
+/// Format of {date}
+const DATE_FORMAT: &str = "%Y-%m-%d";
+/// Length of {short_commit}
+const SHORT_COMMIT_LENGTH: usize = 5;
+
+/// Pattern output lines of a procedure are logged with
+/// Time formats are validated when the configuration is loaded. Unknown placeholders and other braces are kept as is
+#[derive(Debug, Clone)]
+pub struct LogPattern {
+    segments: Vec<Segment>,
+    timezone: LogTimezone,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Name,
+    Path,
+    Command,
+    Log,
+    Branch,
+    Commit,
+    ShortCommit,
+    Stream,
+    Pid,
+    RunId,
+    Env,
+    Event, // Only available in notification messages
+    Time(String), // strftime format, also used for {date}
+}
+
+/// Timezone {time} and {date} are shown in
+#[derive(Debug, Clone)]
+pub enum LogTimezone {
+    Utc,
+    Local,
+    Fixed(FixedOffset),
+}
+
+/// Values of a running command the placeholders are replaced with
+#[derive(Debug, Clone)]
+pub struct LogLineFields {
+    pub procedure_name: String,
+    pub path: String,
+    pub command: String,
+    pub branch: String,
+    pub commit: String,
+    pub pid: Option<u32>, // Only known for commands running on the host Influo runs on
+    pub run_id: String,
+    pub environment: String,
+}
+
+impl LogPattern {
+    /// Reads the log pattern and the log_timezone of a procedure
+    pub fn new(raw_procedure: &Value) -> Result<Option<LogPattern>, Error> {
+        let pattern: &str = match raw_procedure.get("log") {
+            Some(v) => match v.as_str() {
+                Some(s) => s,
+                None => return Err(anyhow!("Log format is invalid in procedure")),
+            },
+            None => return Ok(None)
+        };
+
+        let timezone: LogTimezone = match raw_procedure.get("log_timezone") {
+            Some(v) => match v.as_str() {
+                Some("utc") => LogTimezone::Utc,
+                Some("local") => LogTimezone::Local,
+                Some(offset) => match offset.parse::<FixedOffset>() {
+                    Ok(o) => LogTimezone::Fixed(o),
+                    Err(_) => return Err(anyhow!("Log timezone {} is invalid. Use utc, local or an offset such as +02:00", offset)),
+                },
+                None => return Err(anyhow!("Log timezone is invalid in procedure")),
+            },
+            None => LogTimezone::Utc
+        };
+
+        Ok(Some(LogPattern {
+            segments: parse_segments(pattern, false).map_err(|e| anyhow!("Log format {} is invalid in procedure: {}", pattern, e))?,
+            timezone: timezone,
+        }))
+    }
+
+    /// Template of a notification message which may also use {event}
+    /// {log} is replaced with details of the event such as why the procedure failed
+    pub fn message_template(template: &str) -> Result<LogPattern, Error> {
+        Ok(LogPattern {
+            segments: parse_segments(template, true).map_err(|e| anyhow!("Message {} is invalid: {}", template, e))?,
+            timezone: LogTimezone::Utc,
+        })
+    }
+
+    /// Formats an output line of a stream such as stdout
+    pub fn format(&self, fields: &LogLineFields, stream: &str, line: &str) -> String {
+        self.render(fields, stream, line, "")
+    }
+
+    /// Formats a notification message about an event
+    pub fn format_event(&self, fields: &LogLineFields, event: &str, details: &str) -> String {
+        self.render(fields, "", details, event)
+    }
+
+    fn render(&self, fields: &LogLineFields, stream: &str, line: &str, event: &str) -> String {
+        let mut formatted: String = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(s) => formatted.push_str(s),
+                Segment::Name => formatted.push_str(&fields.procedure_name),
+                Segment::Path => formatted.push_str(&fields.path),
+                Segment::Command => formatted.push_str(&fields.command),
+                Segment::Log => formatted.push_str(line),
+                Segment::Branch => formatted.push_str(&fields.branch),
+                Segment::Commit => formatted.push_str(&fields.commit),
+                Segment::ShortCommit => formatted.push_str(fields.commit.get(..SHORT_COMMIT_LENGTH).unwrap_or(&fields.commit)),
+                Segment::Stream => formatted.push_str(stream),
+                Segment::Pid => match fields.pid {
+                    Some(pid) => formatted.push_str(&pid.to_string()),
+                    None => formatted.push('-'),
+                },
+                Segment::RunId => formatted.push_str(&fields.run_id),
+                Segment::Env => formatted.push_str(&fields.environment),
+                Segment::Event => formatted.push_str(event),
+                Segment::Time(format) => formatted.push_str(&self.format_time(format)),
+            }
+        }
+        formatted
+    }
+
+    fn format_time(&self, format: &str) -> String {
+        match &self.timezone {
+            LogTimezone::Utc => Utc::now().format(format).to_string(),
+            LogTimezone::Local => Local::now().format(format).to_string(),
+            LogTimezone::Fixed(offset) => Utc::now().with_timezone(offset).format(format).to_string(),
+        }
+    }
+}
+
+/// Text in braces which isn't a placeholder is kept as is, like in patterns written before placeholders were validated
+fn parse_segments(pattern: &str, allow_event: bool) -> Result<Vec<Segment>, Error> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut literal: String = String::new();
+    let mut rest: &str = pattern;
+    while let Some(start) = rest.find('{') {
+        literal.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        // A placeholder ends at the next closing brace unless another opening brace comes first
+        let placeholder: Option<&str> = match rest.find(['{', '}']) {
+            Some(end) if rest[end..].starts_with('}') => Some(&rest[..end]),
+            _ => None
+        };
+        let segment: Option<Segment> = match placeholder {
+            Some("event") if allow_event => Some(Segment::Event),
+            Some(p) => parse_placeholder(p)?,
+            None => None
+        };
+        match (segment, placeholder) {
+            (Some(segment), Some(p)) => {
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(segment);
+                rest = &rest[p.len() + 1..];
+            },
+            _ => literal.push('{'),
+        }
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+
+    Ok(segments)
+}
+
+/// Returns None for text which isn't a placeholder
+fn parse_placeholder(placeholder: &str) -> Result<Option<Segment>, Error> {
+    let segment: Segment = match placeholder {
+        "name" => Segment::Name,
+        "path" => Segment::Path,
+        "command" => Segment::Command,
+        "log" => Segment::Log,
+        "branch" => Segment::Branch,
+        "commit" => Segment::Commit,
+        "short_commit" => Segment::ShortCommit,
+        "stream" => Segment::Stream,
+        "pid" => Segment::Pid,
+        "run_id" => Segment::RunId,
+        "env" => Segment::Env,
+        "date" => Segment::Time(DATE_FORMAT.to_string()),
+        "time" => Segment::Time(DEFAULT_TIME_FORMAT.to_string()),
+        _ => match placeholder.strip_prefix("time:") {
+            Some(format) => {
+                if format.is_empty() || StrftimeItems::new(format).any(|i| matches!(i, Item::Error)) {
+                    return Err(anyhow!("Invalid time format {}", format));
+                }
+                Segment::Time(format.to_string())
+            },
+            None => {
+                if !placeholder.is_empty() && placeholder.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+                    warn!("Unknown placeholder {{{}}} is logged as is", placeholder);
+                }
+                return Ok(None);
+            },
+        }
+    };
+
+    Ok(Some(segment))
+}
@@ -3,21 +3,41 @@ use serde_json::Value;
 
 pub mod procedure;
 pub mod branch;
+pub mod checkout;
+pub mod shell;
+pub mod command;
+pub mod limits;
+pub mod user;
+pub mod container;
+pub mod target;
+pub mod log_files;
+pub mod log_pattern;
+pub mod notifier;
+pub mod commit_status;
 
 use self::{
     procedure::Procedure,
-    branch::Branch
+    branch::Branch,
+    checkout::CheckoutOptions,
+    log_files::LogFileOptions,
+    notifier::Notifier,
+    commit_status::CommitStatusOptions
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Project {
     pub url: String,
     pub procedures: Vec<Procedure>,
     pub branches: Vec<Branch>,
+    pub checkout: CheckoutOptions,
+    pub update_interval: Option<u32>, // Overrides the global update interval (milliseconds)
+    pub log_files: Option<LogFileOptions>,
+    pub notifications: Vec<Notifier>, // Sent for events of every procedure of the project
+    pub commit_status: Option<CommitStatusOptions>, // Procedure runs are reported to the git forge if set
 }
 
 impl Project {
-    pub fn new(raw_project: &Value, raw_default_deploy_path: Option<&Value>) -> Result<Project, Error> {
+    pub fn new(raw_project: &Value, raw_config: &Value) -> Result<Project, Error> {
         if !raw_project["url"].is_string() {
             return Err(anyhow!("URL is invalid"));
         }
@@ -29,13 +49,41 @@ impl Project {
         let raw_procedures_array: &Vec<Value> = raw_project["procedures"].as_array().unwrap();
         let mut procedures: Vec<Procedure> = Vec::new();
         for raw_procedure in raw_procedures_array {
-            procedures.push(Procedure::new(raw_procedure, raw_default_deploy_path)?);
+            procedures.push(Procedure::new(raw_procedure, raw_config)?);
         }
 
+        validate_procedure_needs(&procedures)?;
+
+        let checkout: CheckoutOptions = CheckoutOptions::new(raw_project)?;
+
+        let update_interval: Option<u32> = match raw_project.get("update_interval") {
+            Some(raw_update_interval) => match raw_update_interval.as_u64() {
+                Some(interval) => {
+                    if interval == 0 || interval > (std::u32::MAX / 1000) as u64 {
+                        return Err(anyhow!("Update interval is out of range in project"));
+                    }
+                    Some(interval as u32 * 1000)
+                },
+                None => return Err(anyhow!("Update interval is invalid in project")),
+            },
+            None => None
+        };
+
+        let log_files: Option<LogFileOptions> = LogFileOptions::new(raw_config)?;
+
+        let notifications: Vec<Notifier> = Notifier::parse_all(raw_project)?;
+
+        let commit_status: Option<CommitStatusOptions> = CommitStatusOptions::new(raw_project, url)?;
+
         Ok(Project {
             url: url.to_string(),
             procedures: procedures,
             branches: Vec::new(),
+            checkout: checkout,
+            update_interval: update_interval,
+            log_files: log_files,
+            notifications: notifications,
+            commit_status: commit_status,
         })
     }
 
@@ -43,3 +91,33 @@ impl Project {
         self.branches = branches;
     }
 }
+
+/// Makes sure every needed procedure exists and that the needs don't form a cycle
+fn validate_procedure_needs(procedures: &[Procedure]) -> Result<(), Error> {
+    for procedure in procedures {
+        for need in &procedure.needs {
+            if !procedures.iter().any(|p| &p.name == need) {
+                return Err(anyhow!("Procedure {} needs unknown procedure {}", procedure.name, need));
+            }
+        }
+    }
+
+    // Depth first search from every procedure with the current path tracked for cycle detection
+    fn visit<'a>(procedures: &'a [Procedure], name: &'a str, path: &mut Vec<&'a str>) -> Result<(), Error> {
+        if path.contains(&name) {
+            return Err(anyhow!("Procedure needs form a cycle: {} -> {}", path.join(" -> "), name));
+        }
+        path.push(name);
+        let procedure = procedures.iter().find(|p| p.name == name).unwrap();
+        for need in &procedure.needs {
+            visit(procedures, need, path)?;
+        }
+        path.pop();
+        Ok(())
+    }
+    for procedure in procedures {
+        visit(procedures, &procedure.name, &mut Vec::new())?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use anyhow::{Error, anyhow};
+use serde_json::Value;
+
+use super::{
+    shell::Shell,
+    log_pattern::LogPattern,
+    container::parse_string_array
+};
+
+/// Message sent unless a notifier has its own
+const DEFAULT_MESSAGE: &str = "[{name}] {event} on {branch} ({short_commit}) {log}";
+
+/// Lifecycle events of a procedure run which can be notified about
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NotificationEvent {
+    Started,
+    Succeeded,
+    Failed,
+    CrashLooping, // A service keeps exiting and being restarted
+}
+
+impl NotificationEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationEvent::Started => "started",
+            NotificationEvent::Succeeded => "succeeded",
+            NotificationEvent::Failed => "failed",
+            NotificationEvent::CrashLooping => "crash_looping",
+        }
+    }
+
+    fn from_str(event: &str) -> Option<NotificationEvent> {
+        match event {
+            "started" => Some(NotificationEvent::Started),
+            "succeeded" => Some(NotificationEvent::Succeeded),
+            "failed" => Some(NotificationEvent::Failed),
+            "crash_looping" => Some(NotificationEvent::CrashLooping),
+            _ => None
+        }
+    }
+}
+
+/// Where a notification is sent
+#[derive(Debug, Clone)]
+pub enum NotifierKind {
+    Webhook(String), // POSTs the event as JSON
+    Slack(String), // Incoming webhook URL
+    Discord(String), // Incoming webhook URL
+    Email(EmailOptions),
+    Command(String, Shell), // Runs on the host Influo runs on with the event in its environment
+}
+
+#[derive(Debug, Clone)]
+pub struct EmailOptions {
+    pub smtp_url: String, // For example smtps://smtp.example.com:465
+    pub from: String,
+    pub to: Vec<String>,
+    pub username: Option<String>,
+    pub password_env: Option<String>, // Environment variable holding the password so it isn't stored in the configuration
+}
+
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    pub kind: NotifierKind,
+    pub events: Vec<NotificationEvent>, // Every event if not configured
+    pub message: LogPattern,
+}
+
+impl Notifier {
+    /// Reads the notifications of a project or procedure
+    pub fn parse_all(raw_parent: &Value) -> Result<Vec<Notifier>, Error> {
+        let raw_notifiers: &Vec<Value> = match raw_parent.get("notifications") {
+            Some(v) => match v.as_array() {
+                Some(a) => a,
+                None => return Err(anyhow!("Notifications is invalid")),
+            },
+            None => return Ok(Vec::new()),
+        };
+        raw_notifiers.iter().map(Notifier::new).collect()
+    }
+
+    fn new(raw_notifier: &Value) -> Result<Notifier, Error> {
+        let required_string = |key: &str| -> Result<String, Error> {
+            match raw_notifier.get(key).and_then(|v| v.as_str()) {
+                Some(s) if !s.is_empty() => Ok(s.to_string()),
+                _ => Err(anyhow!("Notification {} is missing or invalid", key)),
+            }
+        };
+        let optional_string = |key: &str| -> Result<Option<String>, Error> {
+            match raw_notifier.get(key) {
+                Some(v) => match v.as_str() {
+                    Some(s) => Ok(Some(s.to_string())),
+                    None => Err(anyhow!("Notification {} is invalid", key)),
+                },
+                None => Ok(None)
+            }
+        };
+
+        let kind: NotifierKind = match raw_notifier.get("type").and_then(|t| t.as_str()) {
+            Some("webhook") => NotifierKind::Webhook(required_string("url")?),
+            Some("slack") => NotifierKind::Slack(required_string("url")?),
+            Some("discord") => NotifierKind::Discord(required_string("url")?),
+            Some("email") => {
+                let to: Vec<String> = match raw_notifier.get("to") {
+                    Some(Value::String(s)) => vec![s.clone()],
+                    Some(v) => parse_string_array(Some(v), "Notification to")?,
+                    None => return Err(anyhow!("Notification to is missing")),
+                };
+                if to.is_empty() {
+                    return Err(anyhow!("Notification to is empty"));
+                }
+                NotifierKind::Email(EmailOptions {
+                    smtp_url: required_string("smtp_url")?,
+                    from: required_string("from")?,
+                    to: to,
+                    username: optional_string("username")?,
+                    password_env: optional_string("password_env")?,
+                })
+            },
+            Some("command") => {
+                let command: String = required_string("command")?;
+                let shell: Shell = Shell::new(raw_notifier)?;
+                if let Err(e) = shell.command_line(&command) {
+                    return Err(anyhow!("Notification command is invalid: {}", e));
+                }
+                NotifierKind::Command(command, shell)
+            },
+            _ => return Err(anyhow!("Notification type is invalid. Use webhook, slack, discord, email or command")),
+        };
+
+        let events: Vec<NotificationEvent> = match raw_notifier.get("events") {
+            Some(v) => {
+                let mut events: Vec<NotificationEvent> = Vec::new();
+                for event in parse_string_array(Some(v), "Notification events")? {
+                    match NotificationEvent::from_str(&event) {
+                        Some(e) => events.push(e),
+                        // Influo doesn't roll back deployments so there is nothing to notify about
+                        None if event == "rolled_back" => return Err(anyhow!("Notification event rolled_back is not supported since deployments are never rolled back")),
+                        None => return Err(anyhow!("Notification event {} is invalid. Use started, succeeded, failed or crash_looping", event)),
+                    }
+                }
+                events
+            },
+            None => vec![NotificationEvent::Started, NotificationEvent::Succeeded, NotificationEvent::Failed, NotificationEvent::CrashLooping]
+        };
+
+        let message: LogPattern = LogPattern::message_template(&optional_string("message")?.unwrap_or_else(|| DEFAULT_MESSAGE.to_string()))?;
+
+        Ok(Notifier {
+            kind: kind,
+            events: events,
+            message: message,
+        })
+    }
+}
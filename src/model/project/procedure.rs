@@ -1,16 +1,52 @@
+use std::time::Duration;
 use anyhow::{Error, anyhow};
 use serde_json::Value;
+use glob::{Pattern, MatchOptions};
 
-#[derive(Debug)]
+use crate::executor::lines::{DEFAULT_MAX_LINE_LENGTH, DEFAULT_FLUSH_TIMEOUT};
+
+use super::{
+    shell::Shell,
+    command::ProcedureCommand,
+    limits::{ResourceLimits, parse_byte_size},
+    user::ProcessUser,
+    container::ContainerOptions,
+    target::RemoteTarget,
+    log_pattern::LogPattern,
+    notifier::Notifier
+};
+
+#[derive(Debug, Clone)]
 pub struct Procedure {
     pub name: String,
-    pub commands: Vec<String>,
+    pub commands: Vec<ProcedureCommand>,
+    pub timeout: Option<Duration>, // Deadline for all run commands including retries. The service runs without it
     pub environment: String,
     pub condition: String,
     pub deploy_path: String,
     pub auto_restart: AutoRestartPolicy,
     pub branches: Vec<String>,
-    pub log: Option<String>,
+    pub log: Option<LogPattern>, // Output of commands is only logged if set
+    pub paths: Vec<Pattern>, // If set, at least one changed file must match for the procedure to run
+    pub paths_ignore: Vec<Pattern>, // Changed files matching these are not considered
+    pub shell: Shell,
+    pub needs: Vec<String>, // Procedures which must succeed for the same commit before this one starts
+    pub limits: ResourceLimits,
+    pub user: Option<ProcessUser>, // Commands run as the user Influo runs as if unset
+    pub container: Option<ContainerOptions>, // Commands run inside a container instead of on the host
+    pub target: Option<RemoteTarget>, // Commands run on remote hosts instead of on the host. The deploy path is on the remote hosts
+    pub stderr: StderrMode,
+    pub max_line_length: usize, // Longer output lines are split (bytes)
+    pub line_flush_timeout: Duration, // Output without a trailing newline is emitted after this time
+    pub notifications: Vec<Notifier>, // Sent in addition to the notifications of the project
+}
+
+/// How the stderr of commands is handled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StderrMode {
+    Log, // Logged and written to log files tagged as stderr
+    Merge, // Treated like stdout
+    Suppress, // Read but discarded
 }
 
 #[derive(Debug, Clone)]
@@ -21,8 +57,23 @@ pub enum AutoRestartPolicy {
     InclusionCodes(Vec<i32>), // If the command was unsuccessful and if it is one of the inclusion codes restart
 }
 
+impl AutoRestartPolicy {
+    /// A missing exit code means the command timed out
+    /// Timeouts are restarted unless the policy only lists specific exit codes
+    pub fn should_restart(&self, exit_code: Option<i32>) -> bool {
+        match (self, exit_code) {
+            (AutoRestartPolicy::Always, _) => true,
+            (AutoRestartPolicy::Never, _) => false,
+            (AutoRestartPolicy::ExclusionCodes(excluded_codes), Some(code)) => !excluded_codes.contains(&code),
+            (AutoRestartPolicy::ExclusionCodes(_), None) => true,
+            (AutoRestartPolicy::InclusionCodes(included_codes), Some(code)) => included_codes.contains(&code),
+            (AutoRestartPolicy::InclusionCodes(_), None) => false,
+        }
+    }
+}
+
 impl Procedure {
-    pub fn new(raw_procedure: &Value, raw_default_deploy_path: Option<&Value>) -> Result<Procedure, Error> {
+    pub fn new(raw_procedure: &Value, raw_config: &Value) -> Result<Procedure, Error> {
         let name: &str = match raw_procedure.get("name") {
             Some(raw_name) => match raw_name.as_str() {
                 Some(s) => s,
@@ -31,6 +82,8 @@ impl Procedure {
             None => return Err(anyhow!("Name not found in procedure")),
         };
 
+        let timeout: Option<Duration> = parse_timeout(raw_procedure.get("timeout"))?;
+
         let raw_commands: &Vec<Value> = match raw_procedure.get("commands") {
             Some(v) => match v.as_array() {
                 Some(v) => v,
@@ -38,11 +91,16 @@ impl Procedure {
             },
             None => return Err(anyhow!("Commands not found in procedure")),
         };
-        let mut commands: Vec<String> = Vec::new();
-        for raw_command in raw_commands {
-            match raw_command.as_str() {
-                Some(s) => commands.push(s.to_string()),
-                None => return Err(anyhow!("Procedure command is invalid")),
+        let mut commands: Vec<ProcedureCommand> = Vec::new();
+        for (index, raw_command) in raw_commands.iter().enumerate() {
+            commands.push(ProcedureCommand::new(raw_command, index == raw_commands.len() - 1)?);
+        }
+
+        // Commands are validated now so a quoting mistake doesn't fail a deployment later
+        let shell: Shell = Shell::new(raw_procedure)?;
+        for command in &commands {
+            if let Err(e) = shell.command_line(&command.command) {
+                return Err(anyhow!("Procedure {} has an invalid command: {}", name, e));
             }
         }
 
@@ -67,7 +125,7 @@ impl Procedure {
                 Some(s) => s,
                 None => return Err(anyhow!("Deploy path is invalid in procedure")),
             },
-            None => match raw_default_deploy_path {
+            None => match raw_config.get("default_deploy_path") {
                 Some(raw_default_deploy_path) => match raw_default_deploy_path.as_str() {
                     Some(s) => s,
                     None => return Err(anyhow!("Default deploy path is invalid")),
@@ -152,25 +210,157 @@ impl Procedure {
             }
         }
 
-        let log: Option<String> = match raw_procedure.get("log") {
-            Some(v) => {
-                match v.as_str() {
-                    Some(s) => Some(s.to_string()),
-                    None => return Err(anyhow!("Log format is invalid in procedure")),
-                }
+        let log: Option<LogPattern> = LogPattern::new(raw_procedure)?;
+
+        let stderr: StderrMode = match raw_procedure.get("stderr") {
+            Some(v) => match v.as_str() {
+                Some("log") => StderrMode::Log,
+                Some("merge") => StderrMode::Merge,
+                Some("suppress") => StderrMode::Suppress,
+                _ => return Err(anyhow!("Stderr mode is invalid in procedure. Use log, merge or suppress")),
             },
-            None => None
+            None => StderrMode::Log
         };
 
+        let max_line_length: usize = match raw_procedure.get("max_line_length") {
+            Some(v) => match parse_byte_size(v) {
+                Ok(size) if size >= 64 && size <= usize::MAX as u64 => size as usize,
+                _ => return Err(anyhow!("Max line length is invalid in procedure. It must be at least 64 bytes")),
+            },
+            None => DEFAULT_MAX_LINE_LENGTH
+        };
+
+        let line_flush_timeout: Duration = match raw_procedure.get("line_flush_timeout") {
+            Some(v) => match v.as_f64() {
+                Some(seconds) if seconds > 0.0 && seconds.is_finite() => Duration::from_secs_f64(seconds),
+                _ => return Err(anyhow!("Line flush timeout must be a positive number of seconds")),
+            },
+            None => DEFAULT_FLUSH_TIMEOUT
+        };
+
+        let notifications: Vec<Notifier> = Notifier::parse_all(raw_procedure)?;
+
+        let paths: Vec<Pattern> = parse_path_patterns(raw_procedure, "paths")?;
+        let paths_ignore: Vec<Pattern> = parse_path_patterns(raw_procedure, "paths_ignore")?;
+
+        let mut needs: Vec<String> = Vec::new();
+        if let Some(raw_needs) = raw_procedure.get("needs") {
+            let raw_needs_array: &Vec<Value> = match raw_needs.as_array() {
+                Some(v) => v,
+                None => return Err(anyhow!("Needs is invalid in procedure")),
+            };
+            for raw_need in raw_needs_array {
+                match raw_need.as_str() {
+                    Some(n) => needs.push(n.to_string()),
+                    None => return Err(anyhow!("Procedure need is invalid")),
+                }
+            }
+        }
+
+        let limits: ResourceLimits = ResourceLimits::new(raw_procedure, raw_config.get("cgroup_root"))?;
+        if !limits.is_empty() && cfg!(not(unix)) {
+            warn!("[{}] Resource limits are only supported on Unix and will be ignored", name);
+        }
+
+        let user: Option<ProcessUser> = ProcessUser::new(raw_procedure)?;
+
+        let container: Option<ContainerOptions> = ContainerOptions::new(raw_procedure)?;
+        if container.is_some() {
+            // The runtime only takes a group together with a user. Adding it as a supplementary group wouldn't change the primary group
+            if user.as_ref().is_some_and(|u| u.uid.is_none()) {
+                return Err(anyhow!("Procedure {} with a container needs a user when a group is set", name));
+            }
+            if limits.nice.is_some() {
+                return Err(anyhow!("Nice is not supported for procedure {} with a container", name));
+            }
+        }
+
+        // Remote commands run as the ssh user and are not limited by Influo
+        let target: Option<RemoteTarget> = RemoteTarget::new(raw_procedure)?;
+        if target.is_some() {
+            if container.is_some() {
+                return Err(anyhow!("Procedure {} can't have both a container and a target", name));
+            }
+            if user.is_some() {
+                return Err(anyhow!("Procedure {} can't have a user and a target. Set the user in the target hosts instead", name));
+            }
+            if !limits.is_empty() {
+                return Err(anyhow!("Resource limits are not supported for procedure {} with a target", name));
+            }
+        }
+
         Ok(Procedure {
             name: name.to_string(),
             commands: commands,
+            timeout: timeout,
             environment: environment.to_string(),
             condition: condition.to_string(),
             deploy_path: deploy_path.to_string(),
             auto_restart: auto_restart,
             branches: branches,
             log: log,
+            paths: paths,
+            paths_ignore: paths_ignore,
+            shell: shell,
+            needs: needs,
+            limits: limits,
+            user: user,
+            container: container,
+            target: target,
+            stderr: stderr,
+            max_line_length: max_line_length,
+            line_flush_timeout: line_flush_timeout,
+            notifications: notifications,
         })
     }
+
+    pub fn has_path_filters(&self) -> bool {
+        !self.paths.is_empty() || !self.paths_ignore.is_empty()
+    }
+
+    /// Checks whether any of the changed files between two commits is relevant to the procedure
+    pub fn is_triggered_by(&self, changed_files: &[String]) -> bool {
+        let match_options = MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true, // `*` should not cross directories, `**` should
+            require_literal_leading_dot: false,
+        };
+        changed_files.iter()
+            .filter(|file| !self.paths_ignore.iter().any(|p| p.matches_with(file, match_options)))
+            .any(|file| self.paths.is_empty() || self.paths.iter().any(|p| p.matches_with(file, match_options)))
+    }
+}
+
+/// Timeouts are configured in seconds
+pub fn parse_timeout(raw_timeout: Option<&Value>) -> Result<Option<Duration>, Error> {
+    match raw_timeout {
+        Some(v) => match v.as_u64() {
+            Some(seconds) if seconds > 0 => Ok(Some(Duration::from_secs(seconds))),
+            _ => Err(anyhow!("Timeout must be a positive number of seconds")),
+        },
+        None => Ok(None)
+    }
+}
+
+fn parse_path_patterns(raw_procedure: &Value, key: &str) -> Result<Vec<Pattern>, Error> {
+    let mut patterns: Vec<Pattern> = Vec::new();
+    let raw_patterns: &Vec<Value> = match raw_procedure.get(key) {
+        Some(v) => match v.as_array() {
+            Some(v) => v,
+            None => return Err(anyhow!("Path filter {} is invalid in procedure", key)),
+        },
+        None => return Ok(patterns),
+    };
+    for raw_pattern in raw_patterns {
+        let pattern: &str = match raw_pattern.as_str() {
+            Some(s) => s,
+            None => return Err(anyhow!("Procedure path filter in {} is invalid", key)),
+        };
+        match Pattern::new(pattern) {
+            Ok(p) => patterns.push(p),
+            Err(e) => return Err(anyhow!("Procedure path filter {} in {} is not a valid glob: {}", pattern, key, e)),
+        }
+    }
+
+    Ok(patterns)
 }
@@ -0,0 +1,76 @@
+use anyhow::{Error, anyhow};
+use serde_json::Value;
+
+#[derive(Debug, Clone)]
+pub enum Shell {
+    None, // Commands are split into arguments (POSIX shell quoting) and executed directly
+    Interpreter(String, Vec<String>), // Commands are passed as the last argument to the interpreter program
+}
+
+impl Shell {
+    pub fn new(raw_procedure: &Value) -> Result<Shell, Error> {
+        let raw_shell: &Value = match raw_procedure.get("shell") {
+            Some(v) => v,
+            None => return Ok(Shell::platform_default()),
+        };
+
+        if let Some(raw_interpreter) = raw_shell.as_array() {
+            let mut interpreter: Vec<String> = Vec::new();
+            for raw_argument in raw_interpreter {
+                match raw_argument.as_str() {
+                    Some(s) => interpreter.push(s.to_string()),
+                    None => return Err(anyhow!("Shell interpreter argument is invalid in procedure")),
+                }
+            }
+            if interpreter.is_empty() {
+                return Err(anyhow!("Shell interpreter is empty in procedure"));
+            }
+            let program: String = interpreter.remove(0);
+            return Ok(Shell::Interpreter(program, interpreter));
+        }
+
+        match raw_shell.as_str() {
+            Some("none") => Ok(Shell::None),
+            Some("sh") => Ok(Shell::Interpreter("sh".to_string(), vec!["-c".to_string()])),
+            Some("bash") => Ok(Shell::Interpreter("bash".to_string(), vec!["-c".to_string()])),
+            Some("cmd") => Ok(Shell::Interpreter("cmd".to_string(), vec!["/C".to_string()])),
+            Some("powershell") => Ok(Shell::Interpreter("powershell".to_string(), vec!["-NoProfile".to_string(), "-Command".to_string()])),
+            Some("") => Err(anyhow!("Shell is empty in procedure")),
+            Some(program) => Ok(Shell::Interpreter(program.to_string(), vec!["-c".to_string()])), // Custom POSIX-like interpreter
+            None => Err(anyhow!("Shell is invalid in procedure")),
+        }
+    }
+
+    /// Direct execution on Unix and cmd on Windows, matching previous releases
+    pub fn platform_default() -> Shell {
+        if cfg!(target_os = "windows") {
+            Shell::Interpreter("cmd".to_string(), vec!["/C".to_string()])
+        } else { // Assume Linux, BSD, and OSX
+            Shell::None
+        }
+    }
+
+    /// Builds the program and arguments for running a command
+    /// The first element is the program
+    pub fn command_line(&self, command: &str) -> Result<Vec<String>, Error> {
+        match self {
+            Shell::None => {
+                let args: Vec<String> = match shell_words::split(command) {
+                    Ok(args) => args,
+                    Err(e) => return Err(anyhow!("Command ({}) could not be parsed: {}", command, e)),
+                };
+                if args.is_empty() {
+                    return Err(anyhow!("Command is empty"));
+                }
+                Ok(args)
+            },
+            Shell::Interpreter(program, interpreter_args) => {
+                let mut args: Vec<String> = Vec::with_capacity(interpreter_args.len() + 2);
+                args.push(program.clone());
+                args.extend(interpreter_args.iter().cloned());
+                args.push(command.to_string());
+                Ok(args)
+            }
+        }
+    }
+}
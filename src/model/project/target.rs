@@ -0,0 +1,56 @@
+use anyhow::{Error, anyhow};
+use serde_json::Value;
+
+use super::container::parse_string_array;
+
+/// Remote hosts the repository is checked out on and the commands of the procedure are run on
+/// Hosts are reached using the system ssh client so its configuration and agent are used
+#[derive(Debug, Clone)]
+pub struct RemoteTarget {
+    pub hosts: Vec<String>, // Destinations accepted by ssh such as user@host or an alias from the ssh config
+    pub port: Option<u16>,
+    pub identity_file: Option<String>,
+    pub options: Vec<String>, // Additional arguments for ssh
+}
+
+impl RemoteTarget {
+    pub fn new(raw_procedure: &Value) -> Result<Option<RemoteTarget>, Error> {
+        let raw_target = match raw_procedure.get("target") {
+            Some(v) => match v.as_object() {
+                Some(o) => o,
+                None => return Err(anyhow!("Target is invalid in procedure")),
+            },
+            None => return Ok(None),
+        };
+
+        let hosts: Vec<String> = parse_string_array(raw_target.get("hosts"), "Target hosts")?;
+        if hosts.is_empty() || hosts.iter().any(|h| h.is_empty() || h.starts_with('-')) {
+            return Err(anyhow!("Target hosts are invalid in procedure"));
+        }
+
+        let port: Option<u16> = match raw_target.get("port") {
+            Some(raw_port) => match raw_port.as_u64() {
+                Some(p) if p > 0 && p <= u16::MAX as u64 => Some(p as u16),
+                _ => return Err(anyhow!("Target port is invalid in procedure")),
+            },
+            None => None
+        };
+
+        let identity_file: Option<String> = match raw_target.get("identity_file") {
+            Some(raw_identity_file) => match raw_identity_file.as_str() {
+                Some(s) if !s.is_empty() => Some(s.to_string()),
+                _ => return Err(anyhow!("Target identity_file is invalid in procedure")),
+            },
+            None => None
+        };
+
+        let options: Vec<String> = parse_string_array(raw_target.get("options"), "Target options")?;
+
+        Ok(Some(RemoteTarget {
+            hosts: hosts,
+            port: port,
+            identity_file: identity_file,
+            options: options,
+        }))
+    }
+}
@@ -0,0 +1,118 @@
+use anyhow::{Error, anyhow};
+use serde_json::Value;
+
+/// User and group which procedure commands are run as
+#[derive(Debug, Clone)]
+pub struct ProcessUser {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub user_name: Option<String>, // Used for looking up supplementary groups
+    pub home: Option<String>, // Checkouts run as the user read its git configuration instead of the one of Influo
+}
+
+impl ProcessUser {
+    /// Names are resolved when the configuration is loaded so typos are caught early
+    /// Numeric ids are accepted as well
+    pub fn new(raw_procedure: &Value) -> Result<Option<ProcessUser>, Error> {
+        let raw_user: Option<&str> = match raw_procedure.get("user") {
+            Some(v) => match v.as_str() {
+                Some(s) => Some(s),
+                None => return Err(anyhow!("User is invalid in procedure")),
+            },
+            None => None
+        };
+        let raw_group: Option<&str> = match raw_procedure.get("group") {
+            Some(v) => match v.as_str() {
+                Some(s) => Some(s),
+                None => return Err(anyhow!("Group is invalid in procedure")),
+            },
+            None => None
+        };
+        if raw_user.is_none() && raw_group.is_none() {
+            return Ok(None);
+        }
+        if cfg!(not(unix)) {
+            return Err(anyhow!("Running procedures as a different user or group is only supported on Unix"));
+        }
+
+        let mut process_user = ProcessUser {
+            uid: None,
+            gid: None,
+            user_name: None,
+            home: None,
+        };
+        if let Some(user) = raw_user {
+            let (uid, gid, name, home) = lookup_user(user)?;
+            process_user.uid = Some(uid);
+            process_user.gid = Some(gid); // Primary group unless a group is configured
+            process_user.user_name = name;
+            process_user.home = home;
+        }
+        if let Some(group) = raw_group {
+            process_user.gid = Some(lookup_group(group)?);
+        }
+
+        Ok(Some(process_user))
+    }
+}
+
+/// Returns the uid, primary gid, name and home directory of a user
+#[cfg(unix)]
+fn lookup_user(user: &str) -> Result<(u32, u32, Option<String>, Option<String>), Error> {
+    use std::{ptr, ffi::{CStr, CString}};
+
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer: Vec<libc::c_char> = vec![0; 16384];
+    let mut result: *mut libc::passwd = ptr::null_mut();
+    let status = match user.parse::<u32>() {
+        Ok(uid) => unsafe { libc::getpwuid_r(uid, &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result) },
+        Err(_) => {
+            let name = CString::new(user)?;
+            unsafe { libc::getpwnam_r(name.as_ptr(), &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result) }
+        }
+    };
+    if status != 0 || result.is_null() {
+        // Numeric ids don't have to exist in the user database
+        if let Ok(uid) = user.parse::<u32>() {
+            return Ok((uid, uid, None, None));
+        }
+        return Err(anyhow!("User {} does not exist", user));
+    }
+
+    let name: String = unsafe { CStr::from_ptr(passwd.pw_name) }.to_string_lossy().into_owned();
+    let home: Option<String> = if passwd.pw_dir.is_null() {
+        None
+    } else {
+        Some(unsafe { CStr::from_ptr(passwd.pw_dir) }.to_string_lossy().into_owned())
+    };
+    Ok((passwd.pw_uid, passwd.pw_gid, Some(name), home))
+}
+
+#[cfg(unix)]
+fn lookup_group(group: &str) -> Result<u32, Error> {
+    use std::{ptr, ffi::CString};
+
+    if let Ok(gid) = group.parse::<u32>() {
+        return Ok(gid);
+    }
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer: Vec<libc::c_char> = vec![0; 16384];
+    let mut result: *mut libc::group = ptr::null_mut();
+    let name = CString::new(group)?;
+    let status = unsafe { libc::getgrnam_r(name.as_ptr(), &mut grp, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+    if status != 0 || result.is_null() {
+        return Err(anyhow!("Group {} does not exist", group));
+    }
+
+    Ok(grp.gr_gid)
+}
+
+#[cfg(not(unix))]
+fn lookup_user(_user: &str) -> Result<(u32, u32, Option<String>, Option<String>), Error> {
+    Err(anyhow!("Users are only supported on Unix"))
+}
+
+#[cfg(not(unix))]
+fn lookup_group(_group: &str) -> Result<u32, Error> {
+    Err(anyhow!("Groups are only supported on Unix"))
+}
@@ -0,0 +1,315 @@
+use std::{
+    env,
+    fs::{self, File, OpenOptions, DirBuilder},
+    thread,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio, Child},
+    sync::{Mutex, mpsc::channel, atomic::{AtomicU64, Ordering}},
+    time::{Duration, Instant}
+};
+use anyhow::{Error, anyhow};
+use chrono::{Utc, SecondsFormat};
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+
+use crate::model::project::{
+    log_pattern::LogLineFields,
+    notifier::{Notifier, NotifierKind, NotificationEvent, EmailOptions}
+};
+
+/// Time a notification has to be delivered before it is given up on
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+/// Seconds curl has to complete a request
+const CURL_MAX_TIME: &str = "20";
+/// Bytes of stderr included in the error of a failed notification
+const MAX_ERROR_OUTPUT: u64 = 4096;
+/// Time stderr may still be read after a failed process exited
+const STDERR_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Directory inside the data path emails are written to before curl uploads them
+const MAIL_DIRECTORY: &str = "mail";
+
+/// Emails sent since Influo started. Keeps the names of their files unique
+static MAIL_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref MAIL_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+/// Sets the data path emails are written to. The shared temporary directory isn't used so other users can't tamper with them
+pub fn enable(data_path: &str) {
+    *MAIL_PATH.lock().unwrap() = Some(Path::new(data_path).join(MAIL_DIRECTORY));
+}
+
+/// Sends the notifications subscribed to an event in the background so the procedure isn't held up
+/// The command and pid fields are not used since events belong to the whole procedure run
+pub fn notify(notifiers: &[Notifier], event: NotificationEvent, project_url: &str, fields: &LogLineFields, details: &str) {
+    for notifier in notifiers.iter().filter(|n| n.events.contains(&event)) {
+        let notifier: Notifier = notifier.clone();
+        let project_url: String = project_url.to_string();
+        let fields: LogLineFields = fields.clone();
+        let details: String = details.to_string();
+        thread::spawn(move || {
+            if let Err(e) = send(&notifier, event, &project_url, &fields, &details) {
+                warn!("[{}] Failed to send {} notification: {}", fields.procedure_name, event.as_str(), e);
+            }
+        });
+    }
+}
+
+fn send(notifier: &Notifier, event: NotificationEvent, project_url: &str, fields: &LogLineFields, details: &str) -> Result<(), Error> {
+    let message: String = notifier.message.format_event(fields, event.as_str(), details).trim_end().to_string();
+    match &notifier.kind {
+        NotifierKind::Webhook(url) => post_json(url, &[], &json!({
+            "event": event.as_str(),
+            "project_url": project_url,
+            "branch": fields.branch,
+            "commit": fields.commit,
+            "procedure": fields.procedure_name,
+            "environment": fields.environment,
+            "run_id": fields.run_id,
+            "message": message,
+            "details": details,
+            "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        })),
+        NotifierKind::Slack(url) => post_json(url, &[], &json!({ "text": message })),
+        NotifierKind::Discord(url) => post_json(url, &[], &json!({ "content": message })),
+        NotifierKind::Email(options) => send_email(options, event, fields, &message),
+        NotifierKind::Command(command, shell) => {
+            let args: Vec<String> = shell.command_line(command)?;
+            let child: Child = Command::new(&args[0])
+                .args(&args[1..])
+                .env("INFLUO_EVENT", event.as_str())
+                .env("INFLUO_MESSAGE", &message)
+                .env("INFLUO_DETAILS", details)
+                .env("INFLUO_PROJECT_URL", project_url)
+                .env("INFLUO_BRANCH", &fields.branch)
+                .env("INFLUO_COMMIT", &fields.commit)
+                .env("INFLUO_PROCEDURE", &fields.procedure_name)
+                .env("INFLUO_ENVIRONMENT", &fields.environment)
+                .env("INFLUO_RUN_ID", &fields.run_id)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| anyhow!("Unable to run command ({}): {}", command, e))?;
+            wait(child, &format!("Command ({})", command))
+        }
+    }
+}
+
+/// POSTs a JSON body using curl
+/// The request is passed as a curl config on stdin since webhook URLs and tokens in headers shouldn't show up in the process list
+pub fn post_json(url: &str, headers: &[String], body: &Value) -> Result<(), Error> {
+    let mut config: String = format!(
+        "url = {}\nrequest = \"POST\"\nheader = \"Content-Type: application/json\"\ndata-binary = {}\n",
+        quote_curl_config(url), quote_curl_config(&body.to_string())
+    );
+    for header in headers {
+        config.push_str(&format!("header = {}\n", quote_curl_config(header)));
+    }
+    run_curl(&config)
+}
+
+/// Sends a plain text email over SMTP using curl
+fn send_email(options: &EmailOptions, event: NotificationEvent, fields: &LogLineFields, message: &str) -> Result<(), Error> {
+    let mut config: String = format!("url = {}\nmail-from = {}\n", quote_curl_config(&options.smtp_url), quote_curl_config(&options.from));
+    for recipient in &options.to {
+        config.push_str(&format!("mail-rcpt = {}\n", quote_curl_config(recipient)));
+    }
+    if let Some(username) = &options.username {
+        let password: String = match &options.password_env {
+            Some(variable) => env::var(variable).map_err(|_| anyhow!("Environment variable {} holding the SMTP password is not set", variable))?,
+            None => String::new(),
+        };
+        config.push_str(&format!("user = {}\n", quote_curl_config(&format!("{}:{}", username, password))));
+    }
+
+    // curl reads its config from stdin so the message is uploaded from a file
+    let mail: String = format!(
+        "From: {}\r\nTo: {}\r\nSubject: [{}] {} {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+        options.from, options.to.join(", "), fields.procedure_name, fields.branch, event.as_str(), Utc::now().to_rfc2822(),
+        message.replace('\n', "\r\n")
+    );
+    let mail_path: PathBuf = write_mail(&mail)?;
+    config.push_str(&format!("upload-file = {}\n", quote_curl_config(&mail_path.to_string_lossy())));
+    let result = run_curl(&config);
+    let _ = fs::remove_file(&mail_path);
+    result
+}
+
+/// Writes an email to a new file only Influo can read
+fn write_mail(mail: &str) -> Result<PathBuf, Error> {
+    let directory: PathBuf = match MAIL_PATH.lock().unwrap().clone() {
+        Some(d) => d,
+        None => return Err(anyhow!("Emails can't be sent before the data path is set")),
+    };
+    let mut directory_builder = DirBuilder::new();
+    directory_builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut directory_builder, 0o700);
+    directory_builder.create(&directory).map_err(|e| anyhow!("Unable to create mail directory {}: {}", directory.display(), e))?;
+
+    let mail_path: PathBuf = directory.join(format!("{}-{}.eml", std::process::id(), MAIL_COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let mut file_options = OpenOptions::new();
+    file_options.write(true).create_new(true); // Never follows a link someone placed at the path
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut file_options, 0o600);
+    let mut file: File = file_options.open(&mail_path).map_err(|e| anyhow!("Unable to create email file {}: {}", mail_path.display(), e))?;
+    file.write_all(mail.as_bytes()).map_err(|e| anyhow!("Unable to write email to {}: {}", mail_path.display(), e))?;
+
+    Ok(mail_path)
+}
+
+fn run_curl(config: &str) -> Result<(), Error> {
+    let mut child: Child = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--max-time", CURL_MAX_TIME, "--config", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("Unable to run curl: {}", e))?;
+    // Dropping stdin closes it so curl starts reading the config
+    child.stdin.take().unwrap().write_all(config.as_bytes())?;
+    wait(child, "curl")
+}
+
+/// Waits for a process sending a notification and kills it if it takes too long
+fn wait(mut child: Child, description: &str) -> Result<(), Error> {
+    // Read on a separate thread so a process writing a lot to stderr doesn't block on a full pipe
+    let (stderr_sender, stderr_receiver) = channel::<String>();
+    if let Some(mut pipe) = child.stderr.take() {
+        thread::spawn(move || {
+            let mut stderr: Vec<u8> = Vec::new();
+            let _ = (&mut pipe).take(MAX_ERROR_OUTPUT).read_to_end(&mut stderr);
+            let _ = io::copy(&mut pipe, &mut io::sink()); // The rest is discarded
+            let _ = stderr_sender.send(String::from_utf8_lossy(&stderr).into_owned());
+        });
+    }
+
+    let started: Instant = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if status.success() {
+                return Ok(());
+            }
+            // Processes started in the background may keep the pipe open after the process exited
+            let stderr: String = stderr_receiver.recv_timeout(STDERR_GRACE_PERIOD).unwrap_or_default();
+            return Err(anyhow!("{} failed ({}): {}", description, status, stderr.trim()));
+        }
+        if started.elapsed() > SEND_TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!("{} timed out after {} seconds", description, SEND_TIMEOUT.as_secs()));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Quotes a value of a curl config file
+fn quote_curl_config(value: &str) -> String {
+    let mut quoted: String = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        thread::JoinHandle
+    };
+    use super::*;
+
+    /// Answers a single request with 200 OK and returns its JSON body
+    fn serve_once(listener: TcpListener) -> JoinHandle<Value> {
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length: usize = 0;
+            loop {
+                let mut header: String = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim_end().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body: Vec<u8> = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap();
+            serde_json::from_slice(&body).unwrap()
+        })
+    }
+
+    /// Sends an event to a notifier of the given type pointed at a local stub and returns the body it received
+    fn send_to_stub(raw_notifier: Value) -> Value {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut raw_notifier = raw_notifier;
+        raw_notifier["url"] = Value::from(format!("http://{}/hook", listener.local_addr().unwrap()));
+        let server = serve_once(listener);
+        let notifier: Notifier = Notifier::parse_all(&json!({ "notifications": [raw_notifier] })).unwrap().remove(0);
+        let fields = LogLineFields {
+            procedure_name: "deploy".to_string(),
+            path: "/srv/app".to_string(),
+            command: String::new(),
+            branch: "main".to_string(),
+            commit: "0123456789abcdef".to_string(),
+            pid: None,
+            run_id: "run-1".to_string(),
+            environment: "production".to_string(),
+        };
+        send(&notifier, NotificationEvent::Failed, "https://example.com/app.git", &fields, "exit code 2").unwrap();
+        server.join().unwrap()
+    }
+
+    #[test]
+    fn webhook_body() {
+        let body: Value = send_to_stub(json!({ "type": "webhook" }));
+        assert_eq!(body["event"], "failed");
+        assert_eq!(body["project_url"], "https://example.com/app.git");
+        assert_eq!(body["branch"], "main");
+        assert_eq!(body["commit"], "0123456789abcdef");
+        assert_eq!(body["procedure"], "deploy");
+        assert_eq!(body["environment"], "production");
+        assert_eq!(body["run_id"], "run-1");
+        assert_eq!(body["details"], "exit code 2");
+        assert_eq!(body["message"], "[deploy] failed on main (01234) exit code 2");
+        assert!(body["timestamp"].is_string());
+    }
+
+    #[test]
+    fn slack_body() {
+        let body: Value = send_to_stub(json!({ "type": "slack" }));
+        assert_eq!(body, json!({ "text": "[deploy] failed on main (01234) exit code 2" }));
+    }
+
+    #[test]
+    fn discord_body() {
+        let body: Value = send_to_stub(json!({ "type": "discord" }));
+        assert_eq!(body, json!({ "content": "[deploy] failed on main (01234) exit code 2" }));
+    }
+
+    #[test]
+    fn message_template() {
+        let body: Value = send_to_stub(json!({ "type": "slack", "message": "{env}: {name} {event} at {commit} in {path} ({run_id}) {unknown} {log}" }));
+        assert_eq!(body["text"], "production: deploy failed at 0123456789abcdef in /srv/app (run-1) {unknown} exit code 2");
+    }
+}
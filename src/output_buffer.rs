@@ -0,0 +1,169 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, mpsc::{sync_channel, SyncSender, Receiver}}
+};
+use chrono::{DateTime, Utc, SecondsFormat};
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+
+/// Lines a follower may fall behind by before it is dropped
+const FOLLOWER_BACKLOG: usize = 10000;
+
+lazy_static! {
+    static ref OUTPUT_BUFFERS: Mutex<OutputBuffers> = Mutex::new(OutputBuffers {
+        capacity: 0,
+        procedures: Vec::new(),
+    });
+}
+
+/// Recent output of a procedure shared between its output readers and the control interface
+pub type SharedOutputBuffer = Arc<Mutex<OutputBuffer>>;
+
+/// Buffers of every procedure which ran since Influo started
+struct OutputBuffers {
+    capacity: usize, // Lines kept per procedure. Output isn't buffered if 0
+    procedures: Vec<SharedOutputBuffer>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputLine {
+    pub timestamp: DateTime<Utc>,
+    pub run_id: String,
+    pub stream: String, // stdout, stderr or influo for events of the run
+    pub line: String,
+}
+
+impl OutputLine {
+    /// Same format as lines in log files
+    pub fn to_text(&self) -> String {
+        format!("{} [{}] {}", self.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true), self.stream, self.line)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "timestamp": self.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "run_id": self.run_id,
+            "stream": self.stream,
+            "line": self.line,
+        })
+    }
+}
+
+/// The last lines of output of a procedure on a branch
+/// Lines of previous runs are kept until they are pushed out by newer ones
+pub struct OutputBuffer {
+    pub project_url: String,
+    pub branch: String,
+    pub procedure: String,
+    pub commit: String,
+    pub run_id: String,
+    pub running: bool,
+    lines: VecDeque<OutputLine>,
+    capacity: usize,
+    followers: Vec<SyncSender<OutputLine>>,
+}
+
+impl OutputBuffer {
+    /// Adds a line of the given run. A run which is still stopping may push lines after the next one started
+    pub fn push(&mut self, run_id: &str, stream: &str, line: &str) {
+        let output_line = OutputLine {
+            timestamp: Utc::now(),
+            run_id: run_id.to_string(),
+            stream: stream.to_string(),
+            line: line.to_string(),
+        };
+        // Followers which disconnected dropped their receiver. Followers which can't keep up are dropped so the output of the
+        // procedure isn't held up or queued without bound
+        self.followers.retain(|f| f.try_send(output_line.clone()).is_ok());
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(output_line);
+    }
+
+    /// The last lines, oldest first
+    pub fn lines(&self, limit: usize) -> Vec<OutputLine> {
+        self.lines.iter().skip(self.lines.len().saturating_sub(limit)).cloned().collect()
+    }
+
+    /// Returns the last lines and a receiver for every line pushed afterwards
+    /// The receiver is disconnected if it falls too far behind
+    pub fn follow(&mut self, limit: usize) -> (Vec<OutputLine>, Receiver<OutputLine>) {
+        let (sender, receiver) = sync_channel(FOLLOWER_BACKLOG);
+        self.followers.push(sender);
+        (self.lines(limit), receiver)
+    }
+
+    /// Marks the buffer as not running unless a newer run took it over
+    pub fn finish(&mut self, run_id: &str) {
+        if self.run_id == run_id {
+            self.running = false;
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "project_url": self.project_url,
+            "branch": self.branch,
+            "procedure": self.procedure,
+            "commit": self.commit,
+            "run_id": self.run_id,
+            "running": self.running,
+            "lines": self.lines.len(),
+        })
+    }
+}
+
+/// Starts keeping the given number of lines per procedure
+pub fn enable(capacity: usize) {
+    OUTPUT_BUFFERS.lock().unwrap().capacity = capacity;
+}
+
+/// Returns the buffer a procedure run writes its output to. None if buffering is disabled
+pub fn start_run(project_url: &str, branch: &str, procedure: &str, commit: &str, run_id: &str) -> Option<SharedOutputBuffer> {
+    let mut buffers = OUTPUT_BUFFERS.lock().unwrap();
+    if buffers.capacity == 0 {
+        return None;
+    }
+
+    let existing: Option<SharedOutputBuffer> = buffers.procedures.iter().find(|b| {
+        let b = b.lock().unwrap();
+        b.project_url == project_url && b.branch == branch && b.procedure == procedure
+    }).cloned();
+    let buffer: SharedOutputBuffer = match existing {
+        Some(b) => b,
+        None => {
+            let b = Arc::new(Mutex::new(OutputBuffer {
+                project_url: project_url.to_string(),
+                branch: branch.to_string(),
+                procedure: procedure.to_string(),
+                commit: String::new(),
+                run_id: String::new(),
+                running: false,
+                lines: VecDeque::new(),
+                capacity: buffers.capacity,
+                followers: Vec::new(),
+            }));
+            buffers.procedures.push(Arc::clone(&b));
+            b
+        }
+    };
+    {
+        let mut b = buffer.lock().unwrap();
+        b.commit = commit.to_string();
+        b.run_id = run_id.to_string();
+        b.running = true;
+    }
+
+    Some(buffer)
+}
+
+/// Buffers matching the given project URL, branch and procedure. Unset filters match everything
+pub fn find(project_url: Option<&str>, branch: Option<&str>, procedure: Option<&str>) -> Vec<SharedOutputBuffer> {
+    OUTPUT_BUFFERS.lock().unwrap().procedures.iter().filter(|b| {
+        let b = b.lock().unwrap();
+        project_url.is_none_or(|u| b.project_url == u)
+            && branch.is_none_or(|n| b.branch == n)
+            && procedure.is_none_or(|p| b.procedure == p)
+    }).cloned().collect()
+}
@@ -0,0 +1,172 @@
+use std::{
+    thread,
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock, Condvar}
+};
+use tracing::Span;
+
+use crate::{
+    model::{
+        project::{
+            Project,
+            branch::Branch,
+            procedure::Procedure
+        },
+        channel::{
+            ThreadProcedureConnection,
+            message::{Command, Response}
+        }
+    },
+    procedure_manager::run_project_procedure
+};
+
+pub type ProcedureConnections = Arc<Mutex<Vec<Arc<RwLock<ThreadProcedureConnection>>>>>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StageResult {
+    Succeeded,
+    Failed,
+    Skipped, // A needed procedure failed, was skipped or did not run for the commit
+}
+
+struct PipelineState {
+    cancelled: bool,
+    results: HashMap<String, StageResult>,
+}
+
+/// Procedures triggered by a single commit
+/// Each procedure starts once every procedure it needs finished successfully for the same commit
+pub struct Pipeline {
+    state: Mutex<PipelineState>,
+    stage_finished: Condvar,
+}
+
+impl Pipeline {
+    /// Spawns a stage thread for every procedure and returns a handle for cancelling the pipeline
+    /// Checkouts are serialized using the checkout lock since procedures of a branch share the repository path
+    pub fn start(project: &Project, branch: &Branch, procedures: Vec<Procedure>, connections: ProcedureConnections, checkout_lock: Arc<Mutex<()>>) -> Arc<Pipeline> {
+        let pipeline = Arc::new(Pipeline {
+            state: Mutex::new(PipelineState {
+                cancelled: false,
+                results: HashMap::new(),
+            }),
+            stage_finished: Condvar::new(),
+        });
+
+        let scheduled: Vec<String> = procedures.iter().map(|p| p.name.clone()).collect();
+        let needed_procedures: Vec<String> = procedures.iter().flat_map(|p| p.needs.iter().cloned()).collect();
+        for procedure in procedures {
+            let unscheduled_need: Option<&String> = procedure.needs.iter().find(|n| !scheduled.contains(n));
+            if let Some(need) = unscheduled_need {
+                warn!(procedure = %procedure.name, "[{}] Skipping procedure since the needed procedure {} did not run for commit {}", procedure.name, need, branch.latest_commit_hash);
+                pipeline.finish_stage(&procedure.name, StageResult::Skipped);
+                continue;
+            }
+
+            let needed: bool = needed_procedures.contains(&procedure.name);
+            let stage_pipeline = Arc::clone(&pipeline);
+            let stage_project = project.clone();
+            let stage_branch = branch.clone();
+            let stage_connections = Arc::clone(&connections);
+            let stage_checkout_lock = Arc::clone(&checkout_lock);
+            // Spans aren't inherited by new threads so the stage span is parented explicitly
+            let stage_span = info_span!(parent: Span::current(), "procedure", procedure = %procedure.name);
+            thread::spawn(move || {
+                let _stage_span = stage_span.entered();
+                stage_pipeline.run_stage(&stage_project, &stage_branch, &procedure, needed, stage_connections, stage_checkout_lock);
+            });
+        }
+
+        pipeline
+    }
+
+    /// Prevents procedures which have not started yet from starting
+    pub fn cancel(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.cancelled = true;
+        self.stage_finished.notify_all();
+    }
+
+    fn run_stage(&self, project: &Project, branch: &Branch, procedure: &Procedure, needed: bool, connections: ProcedureConnections, checkout_lock: Arc<Mutex<()>>) {
+        match self.wait_for_needs(procedure) {
+            None => {
+                debug!("[{}] Pipeline for commit {} was cancelled before the procedure started", procedure.name, branch.latest_commit_hash);
+                return;
+            },
+            Some(false) => {
+                warn!(procedure = %procedure.name, "[{}] Skipping procedure since a needed procedure did not succeed", procedure.name);
+                self.finish_stage(&procedure.name, StageResult::Skipped);
+                return;
+            },
+            Some(true) => ()
+        }
+
+        let procedure_connection: Arc<RwLock<ThreadProcedureConnection>> = replace_procedure_connection(project, branch, procedure, &connections);
+        let run_result = {
+            let _checkout_guard = checkout_lock.lock().unwrap();
+            run_project_procedure(project, branch, procedure, Arc::clone(&procedure_connection))
+        };
+        if let Err(e) = run_result {
+            error!("[{}] Procedure failed due to a git error: {}", procedure.name, e);
+            self.finish_stage(&procedure.name, StageResult::Failed);
+            return;
+        }
+
+        // Only wait for the procedure to complete if another procedure depends on it
+        if !needed {
+            return;
+        }
+        let read_connection = procedure_connection.read().unwrap();
+        let mut receiver = read_connection.child_channel.receiver.write().unwrap();
+        let result: StageResult = match futures::executor::block_on(receiver.recv()) {
+            Some(Response::ProcedureCompleted(true)) => StageResult::Succeeded,
+            _ => StageResult::Failed,
+        };
+        self.finish_stage(&procedure.name, result);
+    }
+
+    /// Blocks until every needed procedure finished
+    /// Returns None if the pipeline was cancelled and otherwise whether all of them succeeded
+    fn wait_for_needs(&self, procedure: &Procedure) -> Option<bool> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.cancelled {
+                return None;
+            }
+            if procedure.needs.iter().all(|n| state.results.contains_key(n)) {
+                return Some(procedure.needs.iter().all(|n| state.results[n] == StageResult::Succeeded));
+            }
+            state = self.stage_finished.wait(state).unwrap();
+        }
+    }
+
+    fn finish_stage(&self, procedure_name: &str, result: StageResult) {
+        let mut state = self.state.lock().unwrap();
+        state.results.insert(procedure_name.to_string(), result);
+        self.stage_finished.notify_all();
+    }
+}
+
+/// Kills the previous version of a procedure and registers a connection for the new one
+fn replace_procedure_connection(project: &Project, branch: &Branch, procedure: &Procedure, connections: &ProcedureConnections) -> Arc<RwLock<ThreadProcedureConnection>> {
+    let mut unlocked_connections = connections.lock().unwrap();
+
+    // Kill previous procedure process
+    unlocked_connections.retain(|unlocked_procedure_thread_connection| {
+        let procedure_thread_connection = &unlocked_procedure_thread_connection.read().unwrap();
+        if procedure_thread_connection.remote_url == project.url && procedure_thread_connection.branch == branch.name && procedure_thread_connection.procedure_name == procedure.name {
+            info!("[{}] Found previous running version. Attempting to send kill message", procedure.name);
+            let sen = &procedure_thread_connection.owner_channel.sender.read().unwrap();
+            sen.send(Command::KillProcedure).expect("Failed to send kill command!");
+            // TODO: Wait for response/timeout
+            return false;
+        }
+        true
+    });
+
+    // Insert new connection
+    let procedure_connection = Arc::new(RwLock::new(ThreadProcedureConnection::new(project.url.clone(), branch.name.clone(), procedure.name.clone(), branch.latest_commit_hash.clone())));
+    unlocked_connections.push(Arc::clone(&procedure_connection));
+
+    procedure_connection
+}
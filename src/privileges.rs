@@ -0,0 +1,127 @@
+use anyhow::Error;
+
+use crate::model::project::user::ProcessUser;
+
+/// Drops the privileges of a command to the configured user and group before it is executed
+/// Registered after the resource limits since lowering limits or joining a cgroup may require root
+#[cfg(unix)]
+pub fn apply_process_user(command: &mut tokio::process::Command, user: &ProcessUser) -> Result<(), Error> {
+    let hook = privilege_drop_hook(user)?;
+    unsafe {
+        command.pre_exec(hook);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn apply_process_user(_command: &mut tokio::process::Command, _user: &ProcessUser) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Same as apply_process_user for synchronous commands such as the git commands of a checkout
+#[cfg(unix)]
+pub fn apply_process_user_sync(command: &mut std::process::Command, user: &ProcessUser) -> Result<(), Error> {
+    use std::os::unix::process::CommandExt;
+
+    let hook = privilege_drop_hook(user)?;
+    unsafe {
+        command.pre_exec(hook);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn apply_process_user_sync(_command: &mut std::process::Command, _user: &ProcessUser) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn privilege_drop_hook(user: &ProcessUser) -> Result<impl FnMut() -> std::io::Result<()> + Send + Sync + 'static, Error> {
+    use std::io;
+
+    let uid: Option<libc::uid_t> = user.uid;
+    let gid: Option<libc::gid_t> = user.gid;
+    let groups: Vec<libc::gid_t> = supplementary_groups(user)?;
+
+    // Only async-signal-safe calls are allowed between fork and exec
+    Ok(move || {
+        if !groups.is_empty() && unsafe { libc::setgroups(groups.len() as _, groups.as_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if let Some(g) = gid {
+            if unsafe { libc::setgid(g) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if let Some(u) = uid {
+            if unsafe { libc::setuid(u) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Groups of the user from the group database, always including the configured group
+/// Looked up before forking since reading the group database isn't async-signal-safe
+#[cfg(target_os = "linux")]
+fn supplementary_groups(user: &ProcessUser) -> Result<Vec<libc::gid_t>, Error> {
+    use std::ffi::CString;
+
+    let gid: libc::gid_t = match user.gid {
+        Some(g) => g,
+        None => return Ok(Vec::new()), // Keep the groups of Influo when only the user is unknown
+    };
+    let name: CString = match &user.user_name {
+        Some(n) => CString::new(n.as_str())?,
+        None => return Ok(vec![gid]),
+    };
+
+    let mut count: libc::c_int = 64;
+    loop {
+        let mut groups: Vec<libc::gid_t> = vec![0; count as usize];
+        let status = unsafe { libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+        if status >= 0 {
+            groups.truncate(count as usize);
+            return Ok(groups);
+        }
+        // The buffer was too small and count now holds the required size
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn supplementary_groups(user: &ProcessUser) -> Result<Vec<libc::gid_t>, Error> {
+    Ok(user.gid.into_iter().collect())
+}
+
+/// Hands a checkout directory over to the user its git commands run as
+/// Only the directory itself is changed unless it is still owned by Influo. Such a tree was created by git running as Influo, so it
+/// can't contain links planted by the user which would redirect the recursive change
+#[cfg(unix)]
+pub fn chown_checkout(path: &str, user: &ProcessUser) -> Result<(), Error> {
+    use std::{fs, path::Path, os::unix::fs::{lchown, MetadataExt}};
+
+    fn visit(path: &Path, user: &ProcessUser) -> Result<(), Error> {
+        lchown(path, user.uid, user.gid)?;
+        if fs::symlink_metadata(path)?.is_dir() {
+            for entry in fs::read_dir(path)? {
+                visit(&entry?.path(), user)?;
+            }
+        }
+        Ok(())
+    }
+
+    let owner: u32 = fs::symlink_metadata(path)?.uid();
+    match user.uid {
+        Some(uid) if owner != uid && owner == unsafe { libc::geteuid() } => visit(Path::new(path), user),
+        _ => Ok(lchown(path, user.uid, user.gid)?),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn chown_checkout(_path: &str, _user: &ProcessUser) -> Result<(), Error> {
+    Ok(())
+}
@@ -1,165 +1,465 @@
 use std::{
     thread,
-    process::ExitStatus,
-    sync::{Arc, RwLock}
+    collections::VecDeque,
+    time::{Duration, Instant},
+    sync::{Arc, RwLock, Mutex, atomic::{AtomicU64, Ordering}}
 };
 use anyhow::Error;
-use futures::{select, pin_mut, join, future::FutureExt};
+use futures::{select, pin_mut, join, future::{self, FutureExt}};
 use tokio::{
-    process::{Child, ChildStdout, ChildStderr},
-    runtime::Builder,
-    io::{BufReader, AsyncBufReadExt}
+    runtime::{Builder, Runtime},
+    task::JoinHandle,
+    time::{sleep, timeout}
 };
 use chrono::Utc;
+use tracing::{Span, Instrument};
 
 use crate::{
     model::{
         project::{
             Project,
             branch::Branch,
-            procedure::{Procedure, AutoRestartPolicy},
+            procedure::{Procedure, StderrMode},
+            log_pattern::{LogPattern, LogLineFields},
+            notifier::{Notifier, NotificationEvent},
+            commit_status::CommitStatusOptions,
+            command::{ProcedureCommand, CommandKind},
         },
         channel::{
             Channel,
             ThreadProcedureConnection,
-            message::Command
+            message::{Command, Response}
         }
     },
-    system_cmd::{setup_git_repository, run_procedure_command}
+    system_cmd::{get_repository_name, sanitize_path_component},
+    log_files::ProcedureLog,
+    output_buffer::{self, SharedOutputBuffer},
+    history::{self, RunRecord, RunStatus, Trigger},
+    notifier::notify,
+    commit_status::{self, CommitState},
+    logger::OUTPUT_TARGET,
+    executor::{lines::LineReader, Executor, ExecutorProcess, ExecutionContext, ChildExit, OutputStream, ProcessSignal, create_executor}
 };
 
+/// Time a command has to exit after being asked to before it is killed
+const STOP_GRACE_PERIOD: Duration = Duration::from_secs(10);
+/// Time the output of a command is still read for after it exited
+const OUTPUT_DRAIN_PERIOD: Duration = Duration::from_secs(1);
+/// A service restarted this often within the crash loop window is reported as crash looping
+const CRASH_LOOP_RESTARTS: usize = 5;
+const CRASH_LOOP_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Log file shared by the output readers of every command of a procedure run
+type SharedProcedureLog = Arc<Mutex<ProcedureLog>>;
+
+/// Destinations of the output of a procedure run
+#[derive(Clone)]
+struct OutputSinks {
+    pattern: Option<LogPattern>, // Output is only logged if the procedure has a log pattern
+    file: Option<SharedProcedureLog>,
+    buffer: Option<SharedOutputBuffer>,
+    run_id: String, // Lines are attributed to this run even if a newer run of the procedure shares the buffer
+}
+
+impl OutputSinks {
+    fn is_empty(&self) -> bool {
+        self.pattern.is_none() && self.file.is_none() && self.buffer.is_none()
+    }
+
+    /// Records events of the procedure run between the output of its commands
+    fn write_event(&self, message: &str) {
+        if let Some(file) = &self.file {
+            file.lock().unwrap().write_line("influo", message);
+        }
+        if let Some(buffer) = &self.buffer {
+            buffer.lock().unwrap().push(&self.run_id, "influo", message);
+        }
+    }
+
+    fn write_line(&self, fields: &LogLineFields, stream: &str, line: &str) {
+        if let Some(file) = &self.file {
+            file.lock().unwrap().write_line(stream, line);
+        }
+        if let Some(buffer) = &self.buffer {
+            buffer.lock().unwrap().push(&self.run_id, stream, line);
+        }
+        if let Some(pattern) = &self.pattern {
+            info!(target: OUTPUT_TARGET, stream = stream, "{}", pattern.format(fields, stream, line));
+        }
+    }
+}
+
+/// Procedure runs started since Influo started. Keeps run ids started within the same second unique
+static RUN_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies a run of a procedure in logs and in the environment of its commands
+fn new_run_id() -> String {
+    format!("{}-{}", Utc::now().format("%Y%m%d%H%M%S"), RUN_COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
 pub fn run_project_procedure(project: &Project, branch: &Branch, procedure: &Procedure, procedure_thread_connection: Arc<RwLock<ThreadProcedureConnection>>) -> Result<(), Error> {
-    let repository_name: String = setup_git_repository(&project.url, &procedure.deploy_path, &branch.name)?;
+    let repository_name: String = get_repository_name(&project.url)?;
     let path = format!("{}/{}/{}", procedure.deploy_path, repository_name, branch.name);
-    let commands: Vec<String> = procedure.commands.clone();
+    let commands: Vec<ProcedureCommand> = procedure.commands.clone();
     let procedure_name = procedure.name.clone();
-    let procedure_log = procedure.log.clone();
+    let run_id: String = new_run_id();
+    let stderr_mode: StderrMode = procedure.stderr;
+    let max_line_length: usize = procedure.max_line_length;
+    let line_flush_timeout: Duration = procedure.line_flush_timeout;
     let procedure_restart_policy = procedure.auto_restart.clone();
+    let procedure_timeout: Option<Duration> = procedure.timeout;
+    let procedure_environment: Vec<(String, String)> = vec![
+        ("INFLUO_PROJECT_URL".to_string(), project.url.clone()),
+        ("INFLUO_BRANCH".to_string(), branch.name.clone()),
+        ("INFLUO_COMMIT".to_string(), branch.latest_commit_hash.clone()),
+        ("INFLUO_PROCEDURE".to_string(), procedure.name.clone()),
+        ("INFLUO_ENVIRONMENT".to_string(), procedure.environment.clone()),
+        ("INFLUO_RUN_ID".to_string(), run_id.clone()),
+    ];
+    let context = ExecutionContext {
+        procedure_name: procedure.name.clone(),
+        run_name: sanitize_path_component(&format!("{}-{}-{}", repository_name, branch.name, procedure.name)),
+        run_id: run_id.clone(),
+        deploy_path: procedure.deploy_path.clone(),
+        repository_path: path.clone(),
+        shell: procedure.shell.clone(),
+        environment: procedure_environment,
+        max_line_length: max_line_length,
+        line_flush_timeout: line_flush_timeout,
+    };
+    // Notifications of the project apply to every procedure
+    let notifiers: Vec<Notifier> = project.notifications.iter().chain(procedure.notifications.iter()).cloned().collect();
+    let event_fields = LogLineFields {
+        procedure_name: procedure.name.clone(),
+        path: path.clone(),
+        command: String::new(),
+        branch: branch.name.clone(),
+        commit: branch.latest_commit_hash.clone(),
+        pid: None,
+        run_id: run_id.clone(),
+        environment: procedure.environment.clone(),
+    };
+    let commit_status_options: Option<CommitStatusOptions> = project.commit_status.clone();
+    let mut record = RunRecord::new(&run_id, &project.url, &branch.name, &branch.latest_commit_hash, &procedure.name, Trigger::Poll);
+    let mut executor: Box<dyn Executor> = create_executor(procedure, context);
+    if let Err(e) = executor.checkout(&project.url, &branch.name, &project.checkout) {
+        record.error = Some(e.to_string());
+        history::append(&record);
+        notify(&notifiers, NotificationEvent::Failed, &project.url, &event_fields, &format!("Checkout failed: {}", e));
+        if let Some(options) = &commit_status_options {
+            commit_status::report(options, CommitState::Failure, &event_fields, &format!("Checkout failed: {}", e));
+        }
+        return Err(e);
+    }
+    notify(&notifiers, NotificationEvent::Started, &project.url, &event_fields, "");
+    if let Some(options) = &commit_status_options {
+        commit_status::report(options, CommitState::Pending, &event_fields, &format!("Deploying to {}", procedure.environment));
+    }
+    let log_file: Option<SharedProcedureLog> = match &project.log_files {
+        Some(options) => match ProcedureLog::open(options, &project.url, &procedure.name, &branch.latest_commit_hash) {
+            Ok(l) => Some(Arc::new(Mutex::new(l))),
+            Err(e) => {
+                warn!("[{}] Failed to open log file in {}. Output won't be written to a file: {}", procedure.name, options.directory, e);
+                None
+            }
+        },
+        None => None
+    };
+    let sinks = OutputSinks {
+        pattern: procedure.log.clone(),
+        file: log_file,
+        buffer: output_buffer::start_run(&project.url, &branch.name, &procedure.name, &branch.latest_commit_hash, &run_id),
+        run_id: run_id.clone(),
+    };
+
+    let project_url: String = project.url.clone();
+    let branch_name: String = branch.name.clone();
+    let commit: String = branch.latest_commit_hash.clone();
+    let environment: String = procedure.environment.clone();
 
+    let procedure_span = Span::current();
     thread::spawn(move || {
+        let _procedure_span = procedure_span.entered();
+        let mut failure_reason: Option<String>;
         let mut success = true;
         let mut current_command_index = 0;
+        let mut failed_attempts: u32 = 0;
+        let mut service_restarts: VecDeque<Instant> = VecDeque::new(); // Within the crash loop window
+        let mut crash_loop_notified = false;
+        let deadline: Option<Instant> = procedure_timeout.map(|t| Instant::now() + t);
         loop {
-            let command = &commands[current_command_index];
+            let procedure_command = &commands[current_command_index];
+            let command = &procedure_command.command;
+            let command_span = info_span!("command", command = %command);
+            let _command_span = command_span.enter();
 
-            info!(format!("[{}] [{}] Running command: {}", procedure_name, path, command));
+            // Run commands and their retries share the procedure deadline. The service runs without it
+            let remaining: Option<Duration> = match (deadline, &procedure_command.kind) {
+                (Some(d), CommandKind::Run) => Some(d.saturating_duration_since(Instant::now())),
+                _ => None
+            };
+            if remaining == Some(Duration::ZERO) {
+                record.record_command(command, None, "not_started");
+                failure_reason = Some(format!("was not started since the procedure exceeded its timeout of {} seconds", procedure_timeout.unwrap().as_secs()));
+                warn!("[{}] Command ({}) {}", procedure_name, command, failure_reason.as_ref().unwrap());
+                success = false;
+                break;
+            }
+            let deadline_applies: bool = remaining.is_some_and(|r| procedure_command.timeout.is_none_or(|t| r < t));
+            let command_timeout: Option<Duration> = if deadline_applies { remaining } else { procedure_command.timeout };
+
+            info!("[{}] [{}] Running command: {}", procedure_name, path, command);
+            sinks.write_event(&format!("Running command: {}", command));
             let runtime = Builder::new_multi_thread().enable_all().build().unwrap();
             let _guard = runtime.enter();
-            let result_child_process = run_procedure_command(&command, &path);
-            if result_child_process.is_err() {
+            let result_child_process = executor.spawn(procedure_command);
+            if let Err(e) = result_child_process {
+                error!("[{}] Failed to start command ({}): {}", procedure_name, command, e);
+                failure_reason = Some(format!("failed to start: {}", e));
+                record.record_command(command, None, "not_started");
+                success = false;
                 break;
             }
-            let mut child_process: Child = result_child_process.unwrap();
+            let mut child_process: Box<dyn ExecutorProcess> = result_child_process.unwrap();
 
             // Print stdout and stderr from child process asynchronously
-            if procedure_log.is_some() {
-                let pname: String = procedure_name.clone();
-                let plog: String = procedure_log.clone().unwrap();
-                let p = path.clone();
-                let c = command.clone();
-                let stdout = child_process.stdout.take().expect("Child process stdout handle missing");
-                let stderr = child_process.stderr.take().expect("Child process stderr handle missing");
-                let mut stdout_reader = BufReader::new(stdout);
-                let mut stderr_reader = BufReader::new(stderr);
-                runtime.spawn(async move {
-                    join!(read_stdout(&mut stdout_reader, &pname, &p, &c, &plog), read_stderr(&mut stderr_reader, &pname, &p, &c, &plog));
-                });
+            let mut output_reader: Option<JoinHandle<()>> = None;
+            if !sinks.is_empty() {
+                let command_sinks: OutputSinks = sinks.clone();
+                let fields = LogLineFields {
+                    procedure_name: procedure_name.clone(),
+                    path: path.clone(),
+                    command: command.clone(),
+                    branch: branch_name.clone(),
+                    commit: commit.clone(),
+                    pid: child_process.id(),
+                    run_id: run_id.clone(),
+                    environment: environment.clone(),
+                };
+                let stdout: OutputStream = child_process.stdout().expect("Child process stdout handle missing");
+                let stderr: OutputStream = child_process.stderr().expect("Child process stderr handle missing");
+                let mut stdout_reader = LineReader::new(stdout, max_line_length, line_flush_timeout);
+                let mut stderr_reader = LineReader::new(stderr, max_line_length, line_flush_timeout);
+                output_reader = Some(runtime.spawn(async move {
+                    join!(read_stdout(&mut stdout_reader, &fields, &command_sinks), read_stderr(&mut stderr_reader, &fields, &command_sinks, stderr_mode));
+                }.instrument(command_span.clone())));
             }
 
             // Blocks the thread until the child process running the command has exited
             let read_connection = procedure_thread_connection.read().unwrap();
-            let child_result = runtime.block_on(manage_child(&mut child_process, &read_connection));
-            if !child_result.0 {
-                if let Some(exit_code) = child_result.1 {
-                    let should_restart = match &procedure_restart_policy {
-                        AutoRestartPolicy::Always => true,
-                        AutoRestartPolicy::Never => false,
-                        AutoRestartPolicy::ExclusionCodes(excluded_codes) => {
-                            if !excluded_codes.contains(&exit_code) {
-                                true
-                            } else {
-                                false
-                            }
-                        },
-                        AutoRestartPolicy::InclusionCodes(included_codes) => {
-                            if included_codes.contains(&exit_code) {
-                                true
-                            } else {
-                                false
-                            }
-                        }
-                    };
-                    
-                    if !should_restart {
-                        match runtime.block_on(child_process.kill()) {
-                            Ok(()) => (),
-                            Err(_e) => warn!(format!("[{}] Unable to kill child process. It may already be dead.", procedure_name))
-                        };
-                        info!(format!("[{}] Skipping the remaining commands for project (URL: {}) on branch {} in procedure {}", procedure_name, read_connection.remote_url, read_connection.branch, read_connection.procedure_name));
+            let child_result: ChildResult = runtime.block_on(manage_child(&mut child_process, &read_connection, command_timeout));
+            if let ChildResult::Exited(_) = child_result {
+                drain_output(&runtime, output_reader.take());
+            }
+            let exit_code: Option<i32> = match child_result {
+                ChildResult::Exited(ref exit) if exit.success => {
+                    record.record_command(command, Some(exit.code), "succeeded");
+                    failure_reason = None;
+                    current_command_index += 1;
+                    failed_attempts = 0;
+                    if commands.len() == current_command_index {
+                        break;
+                    }
+                    continue;
+                },
+                ChildResult::Exited(exit) => {
+                    record.record_command(command, Some(exit.code), "failed");
+                    failure_reason = Some(describe_failed_exit(&exit));
+                    warn!("[{}] Command ({}) {}", procedure_name, command, failure_reason.as_ref().unwrap());
+                    sinks.write_event(&format!("Command ({}) {}", command, failure_reason.as_ref().unwrap()));
+                    Some(exit.code)
+                },
+                ChildResult::TimedOut => {
+                    record.record_command(command, None, "timed_out");
+                    failure_reason = Some(if deadline_applies {
+                        format!("timed out since the procedure exceeded its timeout of {} seconds", procedure_timeout.unwrap().as_secs())
+                    } else {
+                        format!("timed out after {} seconds", command_timeout.unwrap().as_secs())
+                    });
+                    warn!("[{}] Command ({}) {}", procedure_name, command, failure_reason.as_ref().unwrap());
+                    sinks.write_event(&format!("Command ({}) {}", command, failure_reason.as_ref().unwrap()));
+                    stop_child(&runtime, &mut child_process, &procedure_name);
+                    drain_output(&runtime, output_reader.take());
+                    // Neither retries nor continue_on_error apply once the procedure ran out of time
+                    if deadline_applies {
                         success = false;
                         break;
                     }
-                } else {
-                    error!(format!("[{}] Encountered unsuccessful child response with missing exit code", procedure_name));
+                    None
+                },
+                ChildResult::Killed => {
+                    record.record_command(command, None, "stopped");
+                    record.status = RunStatus::Stopped;
+                    failure_reason = None;
+                    stop_child(&runtime, &mut child_process, &procedure_name);
+                    drain_output(&runtime, output_reader.take());
+                    info!("[{}] Procedure was stopped. Skipping the remaining commands", procedure_name);
                     success = false;
                     break;
                 }
-            } else {
+            };
+
+            let should_rerun = match procedure_command.kind {
+                // Build steps are retried a limited number of times instead of being restarted
+                CommandKind::Run => {
+                    if failed_attempts < procedure_command.retries {
+                        failed_attempts += 1;
+                        warn!("[{}] Command ({}) failed. Retrying ({}/{})", procedure_name, command, failed_attempts, procedure_command.retries);
+                        true
+                    } else {
+                        false
+                    }
+                },
+                CommandKind::Service => procedure_restart_policy.should_restart(exit_code)
+            };
+
+            if should_rerun {
+                record.restarts += 1;
+            }
+            if should_rerun && procedure_command.kind == CommandKind::Service && !crash_loop_notified {
+                let now: Instant = Instant::now();
+                service_restarts.push_back(now);
+                while service_restarts.front().is_some_and(|r| now.duration_since(*r) > CRASH_LOOP_WINDOW) {
+                    service_restarts.pop_front();
+                }
+                if service_restarts.len() >= CRASH_LOOP_RESTARTS {
+                    let details: String = format!("Command ({}) restarted {} times within {} seconds. Last exit: {}",
+                        command, service_restarts.len(), CRASH_LOOP_WINDOW.as_secs(), failure_reason.as_deref().unwrap_or("unknown"));
+                    warn!("[{}] {}", procedure_name, details);
+                    notify(&notifiers, NotificationEvent::CrashLooping, &project_url, &event_fields, &details);
+                    crash_loop_notified = true;
+                }
+            }
+            if !should_rerun && procedure_command.continue_on_error {
+                warn!("[{}] Ignoring the failure of command ({}) and continuing with the next command", procedure_name, command);
+                failure_reason = None;
                 current_command_index += 1;
+                failed_attempts = 0;
+                if commands.len() == current_command_index {
+                    break;
+                }
+                continue;
             }
-
-            if commands.len() == current_command_index {
+            if !should_rerun {
+                info!("[{}] Skipping the remaining commands for project (URL: {}) on branch {} in procedure {}", procedure_name, read_connection.remote_url, read_connection.branch, read_connection.procedure_name);
+                success = false;
                 break;
             }
         }
         if success {
-            info!(format!("[{}] Work completed successfully!", procedure_name));
+            info!("[{}] Work completed successfully!", procedure_name);
+            sinks.write_event("Work completed successfully");
+        } else if let Some(reason) = &failure_reason {
+            warn!("[{}] Work did not complete. Last command {}.", procedure_name, reason);
+            sinks.write_event(&format!("Work did not complete. Last command {}", reason));
         } else {
-            warn!(format!("[{}] Work did not complete.", procedure_name));
+            warn!("[{}] Work did not complete.", procedure_name);
+            sinks.write_event("Work did not complete");
+        }
+        if let Some(buffer) = &sinks.buffer {
+            buffer.lock().unwrap().finish(&sinks.run_id);
+        }
+        if success {
+            record.status = RunStatus::Succeeded;
+        }
+        record.error = failure_reason.map(|reason| format!("Command ({}) {}", commands[current_command_index].command, reason));
+        history::append(&record);
+        match record.status {
+            RunStatus::Succeeded => notify(&notifiers, NotificationEvent::Succeeded, &project_url, &event_fields, ""),
+            RunStatus::Failed => notify(&notifiers, NotificationEvent::Failed, &project_url, &event_fields, record.error.as_deref().unwrap_or("")),
+            RunStatus::Stopped => {}, // A newer commit or shutdown stopped the run
+        }
+        if let Some(options) = &commit_status_options {
+            let (state, description) = match record.status {
+                RunStatus::Succeeded => (CommitState::Success, format!("Deployed to {}", environment)),
+                RunStatus::Failed => (CommitState::Failure, record.error.clone().unwrap_or_else(|| "Deployment failed".to_string())),
+                RunStatus::Stopped => (CommitState::Canceled, "Stopped before completing".to_string()),
+            };
+            commit_status::report(options, state, &event_fields, &description);
         }
+        drop(executor); // Cleans up before dependent procedures start
+
+        // Let procedures depending on this one know the outcome
+        let completed_connection = procedure_thread_connection.read().unwrap();
+        let _ = completed_connection.child_channel.sender.read().unwrap().send(Response::ProcedureCompleted(success));
     });
 
     Ok(())
 }
 
+/// Result of waiting for a child process
+enum ChildResult {
+    Exited(ChildExit),
+    TimedOut, // The child is still running after the command timeout
+    Killed, // A Command::KillProcedure was received
+}
+
 /// Manages a child and returns a future with the result
-/// Result.0 is if the command was successful
-/// Result.1 is if the command should be rerun
-async fn manage_child(child: &mut Child, connection: &ThreadProcedureConnection) -> (bool, Option<i32>) {
-    let child_completion_future = complete_child(child).fuse();
+async fn manage_child(child: &mut Box<dyn ExecutorProcess>, connection: &ThreadProcedureConnection, timeout: Option<Duration>) -> ChildResult {
+    let child_completion_future = child.wait().fuse();
     let command_exit = process_commands(&connection.owner_channel).fuse();
+    let timeout_future = async {
+        match timeout {
+            Some(duration) => sleep(duration).await,
+            None => future::pending::<()>().await,
+        }
+    }.fuse();
 
-    pin_mut!(child_completion_future, command_exit);
+    pin_mut!(child_completion_future, command_exit, timeout_future);
 
     select! {
-        (success, exit_code) = child_completion_future => {
-            debug!(format!("[{}]: Child exited with code {}", connection.procedure_name, exit_code));
-            return (success, Some(exit_code));
+        exit = child_completion_future => {
+            debug!("[{}]: Child exited with code {}", connection.procedure_name, exit.code);
+            ChildResult::Exited(exit)
         },
         () = command_exit => {
-            debug!(format!("[{}]: Terminating due to Command::KillProcedure", connection.procedure_name));
-            return (false, None);
+            debug!("[{}]: Terminating due to Command::KillProcedure", connection.procedure_name);
+            ChildResult::Killed
+        },
+        () = timeout_future => {
+            debug!("[{}]: Terminating due to timeout", connection.procedure_name);
+            ChildResult::TimedOut
         },
     }
 }
 
-/// Returns a future completed when the child exits
-/// Bool indicates whether it exited successfully
-/// i32 is status code
-async fn complete_child(child: &mut Child) -> (bool, i32) {
-    let status_result: Result<ExitStatus, std::io::Error> = child.wait().await;
-    if status_result.is_err() {
-        return (false, 1);
+/// Asks a child which is still running to exit and kills it after the grace period
+fn stop_child(runtime: &Runtime, child: &mut Box<dyn ExecutorProcess>, procedure_name: &str) {
+    if let Err(e) = child.signal(ProcessSignal::Terminate) {
+        debug!("[{}] Unable to terminate child process: {}", procedure_name, e);
+    }
+    if runtime.block_on(timeout(STOP_GRACE_PERIOD, child.wait())).is_ok() {
+        return;
     }
-    let status = status_result.unwrap();
-    let success: bool = status.success();
-    let raw_code = status.code();
-    let exit_code: i32 = if raw_code.is_some() {
-        raw_code.unwrap()
-    } else {
-        1
+    match child.signal(ProcessSignal::Kill) {
+        Ok(()) => {
+            runtime.block_on(child.wait());
+        },
+        Err(_e) => warn!("[{}] Unable to kill child process. It may already be dead.", procedure_name)
     };
-    return (success, exit_code);
+}
+
+/// Waits for the remaining output of an exited child to be logged
+/// Background processes started by the command may keep the pipes open so this gives up after a while
+fn drain_output(runtime: &Runtime, output_reader: Option<JoinHandle<()>>) {
+    if let Some(reader) = output_reader {
+        let _ = runtime.block_on(timeout(OUTPUT_DRAIN_PERIOD, reader));
+    }
+}
+
+/// Explains why a command failed, including limits being hit
+fn describe_failed_exit(exit: &ChildExit) -> String {
+    if exit.oom_killed {
+        return "was killed by the OOM killer after exceeding its memory limit".to_string();
+    }
+    match exit.signal {
+        #[cfg(unix)]
+        Some(libc::SIGXCPU) => "exceeded its CPU time limit".to_string(),
+        #[cfg(unix)]
+        Some(libc::SIGXFSZ) => "exceeded its file size limit".to_string(),
+        Some(signal) => format!("was terminated by signal {}", signal),
+        None => format!("failed with code {}", exit.code),
+    }
 }
 
 /// Processes incoming messages from the updater thread
@@ -174,29 +474,23 @@ async fn process_commands(connection: &Channel<Command>) {
 }
 
 // STDOUT logging
-async fn read_stdout(stdout_buffer: &mut BufReader<ChildStdout>, procedure_name: &String, path: &String, command: &String, log_pattern: &String) {
-    let mut stdout_reader = stdout_buffer.lines();
-    while let Some(line) = stdout_reader.next_line().await.unwrap() {
-        let out: String = log_pattern
-            .replace("{name}", procedure_name)
-            .replace("{time}", &Utc::now().format("%H:%M:%S").to_string()) // %H:%M:%S can be shortened to %T but that's fine. Additionally, %r will give formatted 12 hour time.
-            .replace("{path}", path)
-            .replace("{command}", command)
-            .replace("{log}", &line);
-        info!(out);
+async fn read_stdout(stdout_reader: &mut LineReader, fields: &LogLineFields, sinks: &OutputSinks) {
+    while let Some(line) = stdout_reader.next_line().await {
+        sinks.write_line(fields, "stdout", &line);
     }
 }
 
 // STDERR logging
-async fn read_stderr(stderr_buffer: &mut BufReader<ChildStderr>, procedure_name: &String, path: &String, command: &String, log_pattern: &String) {
-    let mut stderr_reader = stderr_buffer.lines();
-    while let Some(line) = stderr_reader.next_line().await.unwrap() {
-        let out: String = log_pattern
-            .replace("{name}", procedure_name)
-            .replace("{time}", &Utc::now().format("%H:%M:%S").to_string()) // same note as stdout
-            .replace("{path}", path)
-            .replace("{command}", command)
-            .replace("{log}", &line);
-        error!(out);
+// Suppressed output is still read so the command doesn't block on a full pipe
+async fn read_stderr(stderr_reader: &mut LineReader, fields: &LogLineFields, sinks: &OutputSinks, stderr_mode: StderrMode) {
+    let stream: &str = match stderr_mode {
+        StderrMode::Merge => "stdout",
+        _ => "stderr",
+    };
+    while let Some(line) = stderr_reader.next_line().await {
+        if stderr_mode == StderrMode::Suppress {
+            continue;
+        }
+        sinks.write_line(fields, stream, &line);
     }
 }
@@ -1,114 +1,304 @@
 use std::{
     fs,
-    process::Stdio
+    thread,
+    io::Read,
+    time::{Duration, Instant},
+    process::{Stdio, ExitStatus, Output, Child}
 };
 //use tokio::io::{BufReader, AsyncBufReadExt};
 use anyhow::{Error, anyhow};
 use regex::Regex;
+use chrono::{TimeZone, Utc};
 
-use crate::model::project::branch::Branch;
+use crate::{
+    model::project::{
+        branch::{Branch, CommitMetadata},
+        checkout::{CheckoutOptions, SubmoduleMode},
+        user::ProcessUser
+    },
+    privileges::{apply_process_user_sync, chown_checkout}
+};
 
 /// Synchronous function for running a system command in a child process
 fn run_system_command(command: &str, path: &str) -> Result<String, Error> {
-    let raw_output = if cfg!(target_os = "windows") {
-        std::process::Command::new("cmd")
-                .current_dir(path)
-                .arg("/C")
-                .args(&vec![command])
-                .output()
+    run_system_command_with_timeout(command, path, None)
+}
+
+/// Synchronous function for running a system command in a child process
+/// The child is killed if it has not exited before the timeout
+fn run_system_command_with_timeout(command: &str, path: &str, timeout: Option<Duration>) -> Result<String, Error> {
+    run_system_command_as(command, path, timeout, None)
+}
+
+/// Runs a system command as the given user
+/// Checkouts used by procedures with a user are only touched by git running as that user. Git running as Influo would otherwise
+/// read the configuration and hooks of a repository the user controls
+fn run_system_command_as(command: &str, path: &str, timeout: Option<Duration>, user: Option<&ProcessUser>) -> Result<String, Error> {
+    let mut system_command = if cfg!(target_os = "windows") {
+        let mut c = std::process::Command::new("cmd");
+        c.arg("/C");
+        c
     } else { // Assume Linux, BSD, and OSX
-        std::process::Command::new("sh")
-                .current_dir(path)
-                .arg("-c") // Non-login and non-interactive
-                .args(&vec![command])
-                .output()
+        let mut c = std::process::Command::new("sh");
+        c.arg("-c"); // Non-login and non-interactive
+        c
+    };
+    system_command
+            .current_dir(path)
+            .args(&vec![command])
+            .env("GIT_TERMINAL_PROMPT", "0") // Git should fail instead of waiting for credentials
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+    if let Some(u) = user {
+        if let Some(home) = &u.home {
+            system_command.env("HOME", home);
+        }
+        apply_process_user_sync(&mut system_command, u)?;
+    }
+    let output: Output = match run_with_timeout(&mut system_command, timeout) {
+        Ok(o) => o,
+        Err(e) => {
+            debug!("System command ({}) failed to complete: {}", command, e);
+            return Err(anyhow!("System command failed to complete: {}", e));
+        }
     };
-    let output = raw_output?;
-    if !output.status.success() {
-        let human_exit_code = if output.status.code().is_some() {
-            output.status.code().unwrap()
+    let status: ExitStatus = output.status;
+
+    if !status.success() {
+        let human_exit_code = if status.code().is_some() {
+            status.code().unwrap()
         } else {
             1 // Child process terminated by signal (UNIX) (should probably retrieve signal)
         };
-        debug!(format!("System command failed ({}) with status: {}", command, human_exit_code));
+        debug!("System command failed ({}) with status: {}", command, human_exit_code);
         return Err(anyhow!("System command failure with code {}", human_exit_code));
     }
 
     Ok(String::from_utf8(output.stdout)?)
 }
 
+/// Runs a command in its own process group and collects its piped output
+/// The whole group is killed if the command has not exited before the timeout, so helpers such as git remote helpers or ssh
+/// don't outlive it
+pub fn run_with_timeout(command: &mut std::process::Command, timeout: Option<Duration>) -> Result<Output, Error> {
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(command, 0);
+    let mut child: Child = command.spawn()?;
+
+    // Read the output on separate threads so a full pipe can't block the child
+    let stdout_reader = child.stdout.take().map(|mut stdout| thread::spawn(move || {
+        let mut buffer: Vec<u8> = Vec::new();
+        stdout.read_to_end(&mut buffer).map(|_| buffer)
+    }));
+    let stderr_reader = child.stderr.take().map(|mut stderr| thread::spawn(move || {
+        let mut buffer: Vec<u8> = Vec::new();
+        stderr.read_to_end(&mut buffer).map(|_| buffer)
+    }));
+
+    let started = Instant::now();
+    let status: ExitStatus = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if let Some(t) = timeout {
+            if started.elapsed() >= t {
+                #[cfg(unix)]
+                unsafe {
+                    libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+                }
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(anyhow!("Timed out after {} seconds", t.as_secs()));
+            }
+        }
+        thread::sleep(Duration::from_millis(25));
+    };
+    let mut output = Output {
+        status: status,
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    };
+    if let Some(reader) = stdout_reader {
+        output.stdout = reader.join().map_err(|_| anyhow!("Output reader panicked"))??;
+    }
+    if let Some(reader) = stderr_reader {
+        output.stderr = reader.join().map_err(|_| anyhow!("Output reader panicked"))??;
+    }
+
+    Ok(output)
+}
+
 /// Retrieves the remote git branches synchronously using git ls-remote
-pub fn get_remote_git_repository_commits(remote_url: &str) -> Result<Vec<Branch>, Error> {
-    let result: String = run_system_command(&format!("git ls-remote --heads {}", remote_url), "./")?;
+pub fn get_remote_git_repository_commits(remote_url: &str, timeout: Duration) -> Result<Vec<Branch>, Error> {
+    let result: String = run_system_command_with_timeout(&format!("git ls-remote --heads {}", remote_url), "./", Some(timeout))?;
     let regex_pattern = Regex::new(r"([0-9a-fA-F]+)\s+refs/heads/(\S+)").unwrap();
     let mut branches: Vec<Branch> = Vec::new();
     for capture in regex_pattern.captures_iter(&result) {
         branches.push(Branch {
             name: capture.get(2).unwrap().as_str().to_string(),
-            latest_commit_hash: capture.get(1).unwrap().as_str().to_string()
+            latest_commit_hash: capture.get(1).unwrap().as_str().to_string(),
+            metadata: None,
         });
     }
 
     Ok(branches)
 }
 
-pub fn setup_git_repository(remote_url: &str, project_deploy_path: &str, branch: &str) -> Result<String, Error> {
-    // Download or update repository
+/// Replaces characters which aren't safe in a file name
+pub fn sanitize_path_component(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+        .collect()
+}
+
+/// Returns the path of the mirror for a remote repository inside the data path
+pub fn get_git_mirror_path(data_path: &str, remote_url: &str) -> String {
+    format!("{}/mirrors/{}", data_path, sanitize_path_component(remote_url))
+}
+
+/// Creates or updates a blobless mirror of the remote repository
+/// The mirror is only used to inspect commits (changed files) without touching deployed checkouts
+pub fn update_git_mirror(remote_url: &str, mirror_path: &str, timeout: Duration) -> Result<(), Error> {
+    if fs::metadata(format!("{}/HEAD", mirror_path)).is_ok() {
+        run_system_command_with_timeout("git fetch --prune origin", mirror_path, Some(timeout))?;
+        return Ok(());
+    }
+
+    fs::create_dir_all(mirror_path)?;
+    run_system_command_with_timeout(&format!("git clone --mirror --filter=blob:none {} .", remote_url), mirror_path, Some(timeout))?;
+
+    Ok(())
+}
+
+/// Retrieves the files changed between two commits using a repository mirror
+pub fn get_changed_files(mirror_path: &str, from_commit: &str, to_commit: &str) -> Result<Vec<String>, Error> {
+    let result: String = run_system_command(&format!("git diff --name-only --no-renames {} {}", from_commit, to_commit), mirror_path)?;
+
+    Ok(result.lines().filter(|l| !l.is_empty()).map(|l| l.to_string()).collect())
+}
+
+/// Retrieves the author, timestamp and message of a commit using a repository mirror
+pub fn get_commit_metadata(mirror_path: &str, commit: &str) -> Result<CommitMetadata, Error> {
+    let result: String = run_system_command(&format!("git log -1 --format=%an%x00%ct%x00%B {}", commit), mirror_path)?;
+    let mut fields = result.splitn(3, '\0');
+    let author: String = fields.next().unwrap_or_default().to_string();
+    let raw_timestamp: &str = fields.next().ok_or_else(|| anyhow!("Commit {} metadata is missing a timestamp", commit))?;
+    let message: String = fields.next().unwrap_or_default().trim().to_string();
+    let timestamp = Utc.timestamp_opt(raw_timestamp.parse::<i64>()?, 0).single()
+        .ok_or_else(|| anyhow!("Commit {} has an invalid timestamp", commit))?;
+
+    Ok(CommitMetadata {
+        author: author,
+        message: message,
+        timestamp: timestamp,
+    })
+}
+
+/// Name of the directory a repository is checked out in
+pub fn get_repository_name(remote_url: &str) -> Result<String, Error> {
     let regex_pattern = Regex::new(r"^(https|git)(://|@)([^/:]+)[/:]([^/:]+)/([^.]*)[.git]*?$").unwrap();
     let possible_captures = regex_pattern.captures(remote_url);
     if possible_captures.is_none() {
-        error!(format!("Remote url ({}) did not pass regex", remote_url));
+        error!("Remote url ({}) did not pass regex", remote_url);
         return Err(anyhow!("Remote url ({}) did not pass regex", remote_url));
     }
     let captures = possible_captures.unwrap();
     let possible_repository_name = captures.get(captures.len() - 1);
     if possible_repository_name.is_none() {
-        error!(format!("Remote url ({}) does not contain a valid name", remote_url));
+        error!("Remote url ({}) does not contain a valid name", remote_url);
         return Err(anyhow!("Remote url ({}) does not contain a valid name", remote_url));
     }
-    let repository_name: &str = possible_repository_name.unwrap().as_str();
+
+    Ok(possible_repository_name.unwrap().as_str().to_string())
+}
+
+/// Clones or updates a repository. Git runs as the user if one is given and the checkout is owned by it
+pub fn setup_git_repository(remote_url: &str, project_deploy_path: &str, branch: &str, checkout: &CheckoutOptions, user: Option<&ProcessUser>) -> Result<String, Error> {
+    // Download or update repository
+    let repository_name: String = get_repository_name(remote_url)?;
     let project_path: String = format!("{}/{}", project_deploy_path, repository_name);
 
     // Make sure the deploy path is valid
     fs::create_dir_all(&project_path)?;
+    if let Some(u) = user {
+        chown_checkout(&project_path, u)?;
+    }
 
-    let clone_attempt = run_system_command(&format!("git clone --single-branch --branch {} {} {}", branch, remote_url, branch), &project_path);
-    if clone_attempt.is_err() {
-        if let Err(e0) = clone_attempt {
-            debug!(format!("Git clone attempt failed for {} due to: {}", remote_url, e0));
-            let pull_attempt = run_system_command(&format!("git pull origin {}", branch), &format!("{}/{}", project_path, branch));
-            if pull_attempt.is_err() {
-                if let Err(e1) = pull_attempt {
-                    debug!(format!("Git pull attempt failed for {} due to: {}", remote_url, e1));
-                    error!(format!("Failed to update/create git repository with URL: {} and branch: {} in path: {}", remote_url, branch, project_path));
-                    return Err(anyhow!("Failed to update/create git repository with URL: {} and branch: {} in path: {}", remote_url, branch, project_path));
-                }
+    let depth_flag: String = match checkout.depth {
+        Some(depth) => format!(" --depth {}", depth),
+        None => String::new()
+    };
+    let sparse_flag: &str = if checkout.sparse_paths.is_empty() {
+        ""
+    } else {
+        " --sparse" // Only top level files are checked out until the sparse checkout is set
+    };
+    // Existing checkouts are reset to the fetched branch. Pulling would fail on force pushes and once a shallow history moves past its depth
+    let repository_path: String = format!("{}/{}", project_path, branch);
+    let update_attempt = if fs::metadata(format!("{}/.git", repository_path)).is_ok() {
+        run_system_command_as(&format!("git fetch{} origin {}", depth_flag, branch), &repository_path, Some(checkout.timeout), user)
+            .and_then(|_| run_system_command_as("git reset --hard FETCH_HEAD", &repository_path, Some(checkout.timeout), user))
+    } else {
+        run_system_command_as(&format!("git clone --single-branch{}{} --branch {} {} {}", depth_flag, sparse_flag, branch, remote_url, branch), &project_path, Some(checkout.timeout), user)
+    };
+    if let Err(e) = update_attempt {
+        debug!("Git clone/fetch attempt failed for {} due to: {}", remote_url, e);
+        error!("Failed to update/create git repository with URL: {} and branch: {} in path: {}", remote_url, branch, project_path);
+        return Err(anyhow!("Failed to update/create git repository with URL: {} and branch: {} in path: {}", remote_url, branch, project_path));
+    }
+
+    if let Err(e) = update_sparse_checkout(&repository_path, checkout, user) {
+        error!("Failed to update sparse checkout for git repository with URL: {} and branch: {} in path: {}", remote_url, branch, project_path);
+        return Err(e);
+    }
+    if let Err(e) = update_git_repository_extras(&repository_path, checkout, user) {
+        error!("Failed to fetch submodules/LFS objects for git repository with URL: {} and branch: {} in path: {}", remote_url, branch, project_path);
+        return Err(e);
+    }
+
+    Ok(repository_name)
+}
+
+/// Applies the sparse checkout paths to a cloned or updated repository
+/// Paths are reapplied on every update so configuration changes take effect without a fresh clone
+fn update_sparse_checkout(repository_path: &str, checkout: &CheckoutOptions, user: Option<&ProcessUser>) -> Result<(), Error> {
+    if checkout.sparse_paths.is_empty() {
+        // Restore the full working tree if the repository was previously sparse
+        let sparse_enabled = run_system_command_as("git config --get core.sparseCheckout", repository_path, Some(checkout.timeout), user);
+        if let Ok(value) = sparse_enabled {
+            if value.trim() == "true" {
+                run_system_command_as("git sparse-checkout disable", repository_path, Some(checkout.timeout), user)?;
             }
         }
+        return Ok(());
     }
 
-    Ok(repository_name.to_string())
+    let paths: String = shell_words::join(&checkout.sparse_paths);
+    run_system_command_as("git sparse-checkout init --cone", repository_path, Some(checkout.timeout), user)?;
+    run_system_command_as(&format!("git sparse-checkout set {}", paths), repository_path, Some(checkout.timeout), user)?;
+
+    Ok(())
 }
 
-/// Special system command runner for long running children
-/// Procedure commands are not guaranteed to end
-pub fn run_procedure_command(command: &str, repository_path: &str) -> Result<tokio::process::Child, Error> {
-    if cfg!(target_os = "windows") {
-        Ok(tokio::process::Command::new("cmd")
-                .current_dir(repository_path)
-                .arg("/C")
-                .args(&vec![command])
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .spawn()?)
-    } else { // Assume Linux, BSD, and OSX
-        let args = shell_words::split(command).unwrap();
-        Ok(tokio::process::Command::new(&args[0])
-                .current_dir(repository_path)
-                //.arg("-c") // Non-login and non-interactive
-                .args(if args.len() == 0 { &[][..] } else { &args[1..] })
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .spawn()?)
+/// Initializes submodules and fetches LFS objects for a cloned or updated repository
+/// Both are optional and controlled by the project checkout options
+fn update_git_repository_extras(repository_path: &str, checkout: &CheckoutOptions, user: Option<&ProcessUser>) -> Result<(), Error> {
+    if checkout.submodules != SubmoduleMode::Disabled {
+        let recursive_flag: &str = if checkout.submodules == SubmoduleMode::Recursive {
+            " --recursive"
+        } else {
+            ""
+        };
+        // Sync first so that changes to submodule URLs in .gitmodules are picked up on updates
+        run_system_command_as(&format!("git submodule sync{}", recursive_flag), repository_path, Some(checkout.timeout), user)?;
+        run_system_command_as(&format!("git submodule update --init --force{}", recursive_flag), repository_path, Some(checkout.timeout), user)?;
     }
+
+    if checkout.lfs {
+        run_system_command_as("git lfs install --local", repository_path, Some(checkout.timeout), user)?;
+        run_system_command_as("git lfs pull", repository_path, Some(checkout.timeout), user)?;
+    }
+
+    Ok(())
 }